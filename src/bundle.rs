@@ -0,0 +1,427 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use sqlite::{Connection, Value};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::config;
+use crate::db::Db;
+use crate::library::Library;
+use crate::schema;
+
+/// Layout of the bundle, bumped whenever it changes.
+const FORMAT: i64 = 1;
+/// Columns holding paths that move with their root.
+const PATHS: [(&str, &str); 4] = [
+    ("file", "path"),
+    ("dir", "path"),
+    ("library_root", "path"),
+    ("scan_run", "root"),
+];
+
+/// The first entry of a bundle, `key = value` lines.
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub format: i64,
+    /// Schema version of the database inside.
+    pub schema: i64,
+    /// `(library, root)` as they were on the machine the bundle was made on.
+    pub roots: Vec<(String, String)>,
+}
+
+impl Manifest {
+    fn parse(content: &str) -> Result<Self, String> {
+        let mut manifest = Manifest {
+            format: 0,
+            schema: 0,
+            roots: vec![],
+        };
+
+        for (key, value) in content.lines().filter_map(|l| l.split_once(" = ")) {
+            match key {
+                "format" => manifest.format = value.parse().map_err(|_| "bad format line")?,
+                "schema" => manifest.schema = value.parse().map_err(|_| "bad schema line")?,
+                "root" => {
+                    let (library, path) = value.split_once('\t').ok_or("bad root line")?;
+                    manifest.roots.push((library.to_string(), path.to_string()));
+                }
+                _ => (),
+            }
+        }
+
+        Ok(manifest)
+    }
+}
+
+impl std::fmt::Display for Manifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "format = {}", self.format)?;
+        writeln!(f, "schema = {}", self.schema)?;
+        for (library, path) in self.roots.iter() {
+            writeln!(f, "root = {}\t{}", library, path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Db {
+    /// Writes a library, or the whole index for `None`, into one archive: a
+    /// manifest with the format, the schema version and the roots, and a
+    /// compact copy of the database with the kept texts, postings and
+    /// metadata.
+    pub fn export(&self, bundle: &Path, library: Option<&str>) -> Result<Manifest, String> {
+        let libraries: Vec<Library> = self
+            .libraries()
+            .into_iter()
+            .filter(|l| library.is_none_or(|name| l.name == name))
+            .collect();
+        if let (Some(name), true) = (library, libraries.is_empty()) {
+            return Err(format!("No library named `{}`", name));
+        }
+
+        let copy = config::get().data_dir.join("export.db");
+        let _ = fs::remove_file(&copy);
+        let mut stat = self.pool.prepare("VACUUM INTO ?;").unwrap();
+        stat.bind((1, copy.to_str().unwrap()))
+            .map_err(|e| e.to_string())?;
+        stat.next().map_err(|e| e.to_string())?;
+        drop(stat);
+
+        if let Some(name) = library {
+            let copy = Db {
+                pool: Connection::open(&copy).map_err(|e| e.to_string())?,
+            };
+            copy.keep_library(name);
+        }
+
+        let manifest = Manifest {
+            format: FORMAT,
+            schema: schema::current_version(&self.pool),
+            roots: libraries
+                .into_iter()
+                .flat_map(|l| l.roots.into_iter().map(move |r| (l.name.clone(), r.path)))
+                .collect(),
+        };
+
+        let written = write_bundle(bundle, &manifest, &copy);
+        let _ = fs::remove_file(&copy);
+        written.map_err(|e| format!("Can't write {}: {}", bundle.display(), e))?;
+
+        Ok(manifest)
+    }
+    /// Drops every library but `name` from a copy of the index, then the
+    /// words only they used.
+    fn keep_library(&self, name: &str) {
+        self.pool.execute("BEGIN IMMEDIATE;").unwrap();
+        let others = "library_id NOT IN (SELECT rowid FROM library WHERE name=?)";
+        self.remove_files(others, &[Value::String(name.to_string())]);
+        for table in ["dir", "library_root", "scan_run"] {
+            let query = format!("DELETE FROM {} WHERE {};", table, others);
+            let mut stat = self.pool.prepare(query).unwrap();
+            stat.bind((1, name)).unwrap();
+            stat.next().expect("Err during exporting library");
+        }
+        let mut stat = self
+            .pool
+            .prepare("DELETE FROM library WHERE name!=?;")
+            .unwrap();
+        stat.bind((1, name)).unwrap();
+        stat.next().expect("Err during exporting library");
+        self.pool.execute("COMMIT;").unwrap();
+
+        self.compact();
+    }
+    /// Adds the libraries of an unpacked bundle to the index. A library of
+    /// the same name is replaced, the others are kept as they are. Files are
+    /// re-indexed from their kept texts when the bundle cut words otherwise.
+    fn merge(&self, database: &Path) {
+        let mut stat = self.pool.prepare("ATTACH DATABASE ? AS bundle;").unwrap();
+        stat.bind((1, database.to_str().unwrap())).unwrap();
+        stat.next().expect("Err during opening bundle");
+        drop(stat);
+
+        let max_rowid = |table: &str| {
+            self.pool
+                .prepare(format!(
+                    "SELECT IFNULL(max(rowid), 0) AS max FROM {};",
+                    table
+                ))
+                .unwrap()
+                .into_iter()
+                .map(|r| r.unwrap().read::<i64, _>("max"))
+                .next()
+                .unwrap()
+        };
+        let first_term = max_rowid("main.term") + 1;
+        let first_file = max_rowid("main.file") + 1;
+        let setting = |schema: &str, key: &str| {
+            let query = format!("SELECT value FROM {}.setting WHERE key=?;", schema);
+            let mut stat = self.pool.prepare(query).unwrap();
+            stat.bind((1, key)).unwrap();
+            stat.into_iter()
+                .map(|r| r.unwrap().read::<&str, _>("value").to_string())
+                .next()
+        };
+        let same_words = ["word_tokenizer", "word_tokenizer_version"]
+            .iter()
+            .all(|key| setting("main", key) == setting("bundle", key));
+
+        let libraries: Vec<(i64, String)> = self
+            .pool
+            .prepare("SELECT rowid, name FROM bundle.library;")
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap())
+            .map(|r| {
+                (
+                    r.read::<i64, _>("rowid"),
+                    r.read::<&str, _>("name").to_string(),
+                )
+            })
+            .collect();
+
+        self.pool.execute("BEGIN IMMEDIATE;").unwrap();
+        let query = "
+	INSERT OR IGNORE INTO main.term(term, stem)
+		SELECT term, stem FROM bundle.term WHERE rowid IN (SELECT term_id FROM bundle.posting);
+	CREATE TEMP TABLE IF NOT EXISTS import_map(old_library INTEGER, library INTEGER, files INTEGER);
+	";
+        self.pool.execute(query).unwrap();
+        for (old_id, name) in libraries {
+            let library_id = match self.libraries().into_iter().find(|l| l.name == name) {
+                Some(library) => {
+                    self.clear_library(library.id);
+                    library.id
+                }
+                None => self.create_library(&name),
+            };
+            self.pool.execute("DELETE FROM import_map;").unwrap();
+            for query in [
+                "
+	UPDATE main.library SET (follow_symlinks, same_filesystem, dedupe_hardlinks) =
+		(SELECT follow_symlinks, same_filesystem, dedupe_hardlinks FROM bundle.library WHERE rowid=?1)
+		WHERE rowid=?2;
+	",
+                "
+	INSERT INTO import_map VALUES(?1, ?2, (SELECT IFNULL(max(rowid), 0) FROM main.file));
+	",
+            ] {
+                let mut stat = self.pool.prepare(query).unwrap();
+                stat.bind((1, old_id)).unwrap();
+                stat.bind((2, library_id)).unwrap();
+                stat.next().expect("Err during importing library");
+            }
+            self.copy_library();
+        }
+
+        let terms: Vec<(i64, String)> = {
+            let mut stat = self
+                .pool
+                .prepare("SELECT rowid, term FROM main.term WHERE rowid>=?;")
+                .unwrap();
+            stat.bind((1, first_term)).unwrap();
+            stat.into_iter()
+                .map(|r| r.unwrap())
+                .map(|r| {
+                    (
+                        r.read::<i64, _>("rowid"),
+                        r.read::<&str, _>("term").to_string(),
+                    )
+                })
+                .collect()
+        };
+        for (term_id, term) in terms.iter() {
+            self.insert_term_grams(*term_id, term);
+        }
+        self.pool.execute("COMMIT;").unwrap();
+        self.pool.execute("DETACH DATABASE bundle;").unwrap();
+
+        if !same_words {
+            self.retokenize_files("file.rowid>=?", &[Value::Integer(first_file)]);
+        }
+    }
+    /// Drops everything a library has indexed before a bundle replaces it.
+    fn clear_library(&self, library_id: i64) {
+        self.remove_files("library_id=?", &[Value::Integer(library_id)]);
+        for table in ["dir", "library_root", "scan_run"] {
+            let query = format!("DELETE FROM main.{} WHERE library_id=?;", table);
+            let mut stat = self.pool.prepare(query).unwrap();
+            stat.bind((1, library_id)).unwrap();
+            stat.next().expect("Err during importing library");
+        }
+    }
+    /// Copies the library of `import_map` from the bundle. Files get rowids
+    /// after those of the index, terms are matched by their text. The runs
+    /// files were seen in are forgotten, the vacuumed bundle may have
+    /// numbered its runs anew.
+    fn copy_library(&self) {
+        let library = "(SELECT library FROM import_map)";
+        let old_library = "library_id=(SELECT old_library FROM import_map)";
+        let files = "(SELECT files FROM import_map)";
+        let live = format!(
+            "file_id IN (SELECT rowid FROM bundle.file WHERE deleted=0 AND {})",
+            old_library
+        );
+
+        self.copy_rows("library_root", &[("library_id", library)], old_library);
+        self.copy_rows("dir", &[("library_id", library)], old_library);
+        self.copy_rows("scan_run", &[("library_id", library)], old_library);
+        self.copy_rows(
+            "file",
+            &[
+                ("rowid", &format!("rowid + {}", files)),
+                ("library_id", library),
+                ("seen_run", "NULL"),
+            ],
+            &format!("deleted=0 AND {}", old_library),
+        );
+        for table in ["file_text", "token"] {
+            self.copy_rows(
+                table,
+                &[("file_id", &format!("file_id + {}", files))],
+                &live,
+            );
+        }
+
+        let query = format!(
+            "
+	INSERT INTO main.posting
+		SELECT term.rowid, posting.file_id + {0}, posting.position FROM bundle.posting
+		JOIN bundle.term AS bundle_term ON bundle_term.rowid = posting.term_id
+		JOIN main.term ON term.term = bundle_term.term
+		WHERE posting.{1};
+	INSERT INTO main.file_fts(rowid, content)
+		SELECT rowid + {0}, content FROM bundle.file WHERE deleted=0 AND {2};
+	",
+            files, live, old_library
+        );
+        self.pool.execute(query).unwrap();
+    }
+    /// Copies the rows of a bundle table matching `condition`, computing the
+    /// columns of `set` by their expressions.
+    fn copy_rows(&self, table: &str, set: &[(&str, &str)], condition: &str) {
+        let mut columns: Vec<String> = set
+            .iter()
+            .filter(|(column, _)| *column == "rowid")
+            .map(|(column, _)| column.to_string())
+            .collect();
+        columns.extend(
+            self.pool
+                .prepare(format!("PRAGMA main.table_info({});", table))
+                .unwrap()
+                .into_iter()
+                .map(|r| r.unwrap().read::<&str, _>("name").to_string()),
+        );
+        let values: Vec<&str> = columns
+            .iter()
+            .map(|column| {
+                set.iter()
+                    .find(|(set, _)| set == column)
+                    .map_or(column.as_str(), |(_, value)| value)
+            })
+            .collect();
+
+        let query = format!(
+            "INSERT INTO main.{0}({1}) SELECT {2} FROM bundle.{0} WHERE {3};",
+            table,
+            columns.join(", "),
+            values.join(", "),
+            condition
+        );
+        self.pool.execute(query).unwrap();
+    }
+}
+
+fn write_bundle(bundle: &Path, manifest: &Manifest, database: &Path) -> io::Result<()> {
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut zip = ZipWriter::new(File::create(bundle)?);
+
+    zip.start_file("manifest.txt", options)?;
+    zip.write_all(manifest.to_string().as_bytes())?;
+    zip.start_file("database.db", options)?;
+    io::copy(&mut File::open(database)?, &mut zip)?;
+    zip.finish()?;
+
+    Ok(())
+}
+
+/// Adds the libraries of a bundle to the index, replacing the ones of the
+/// same name. Paths under each `(old, new)` root of `rebase` are moved to the
+/// new root, other roots keep their paths.
+pub fn import(bundle: &Path, rebase: &[(String, String)]) -> Result<Manifest, String> {
+    let file = File::open(bundle).map_err(|e| format!("Can't open {}: {}", bundle.display(), e))?;
+    let mut zip = ZipArchive::new(file).map_err(|e| format!("Not a bundle: {}", e))?;
+
+    let mut content = String::new();
+    zip.by_name("manifest.txt")
+        .map_err(|_| "Not a bundle: no manifest")?
+        .read_to_string(&mut content)
+        .map_err(|e| e.to_string())?;
+    let manifest = Manifest::parse(&content)?;
+    if manifest.format != FORMAT {
+        return Err(format!("Unsupported bundle format {}", manifest.format));
+    }
+
+    let config = config::get();
+    let copy = config.data_dir.join("import.db");
+    {
+        let mut database = zip
+            .by_name("database.db")
+            .map_err(|_| "Not a bundle: no database")?;
+        let mut out = File::create(&copy).map_err(|e| e.to_string())?;
+        io::copy(&mut database, &mut out).map_err(|e| e.to_string())?;
+    }
+
+    let rebased = rebase_roots(&copy, &manifest, rebase);
+    if rebased.is_ok() {
+        let db = Db::new();
+        db.merge(&copy);
+        db.count_terms();
+    }
+    let _ = fs::remove_file(&copy);
+    rebased?;
+
+    Ok(manifest)
+}
+
+/// Moves the paths under the roots of `rebase` to their new place.
+fn rebase_roots(
+    database: &Path,
+    manifest: &Manifest,
+    rebase: &[(String, String)],
+) -> Result<(), String> {
+    let conn = Connection::open(database).map_err(|e| e.to_string())?;
+    if schema::current_version(&conn) > schema::latest_version() {
+        return Err(format!(
+            "The bundle was made by a newer version (schema {})",
+            manifest.schema
+        ));
+    }
+    // a bundle made by an older version is brought up to date first
+    schema::migrate(&conn);
+
+    conn.execute("BEGIN;").map_err(|e| e.to_string())?;
+    for (old, new) in rebase {
+        if !manifest.roots.iter().any(|(_, root)| root == old) {
+            return Err(format!("`{}` is not a root of the bundle", old));
+        }
+        let prefix = Path::new(old).join("");
+
+        for (table, column) in PATHS {
+            let query = format!(
+                "UPDATE {0} SET {1}=?2 || substr({1}, length(?1) + 1) WHERE {1}=?1 OR substr({1}, 1, length(?3))=?3;",
+                table, column
+            );
+            let mut stat = conn.prepare(query).map_err(|e| e.to_string())?;
+            stat.bind((1, old.as_str())).unwrap();
+            stat.bind((2, new.as_str())).unwrap();
+            stat.bind((3, prefix.to_str().unwrap())).unwrap();
+            stat.next().map_err(|e| e.to_string())?;
+        }
+    }
+    conn.execute("COMMIT;").map_err(|e| e.to_string())?;
+
+    Ok(())
+}
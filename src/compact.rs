@@ -0,0 +1,102 @@
+use std::fmt;
+
+use crate::db::Db;
+
+#[derive(Debug, Default, Clone)]
+pub struct CompactReport {
+    /// Tombstoned file rows dropped.
+    pub files: i64,
+    /// Terms no file uses anymore.
+    pub terms: i64,
+    pub bytes_before: i64,
+    pub bytes_after: i64,
+}
+
+impl CompactReport {
+    pub fn reclaimed(&self) -> i64 {
+        self.bytes_before - self.bytes_after
+    }
+}
+
+impl fmt::Display for CompactReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Dropped {} removed files and {} unused terms, {} -> {} ({} reclaimed)",
+            self.files,
+            self.terms,
+            human_size(self.bytes_before),
+            human_size(self.bytes_after),
+            human_size(self.reclaimed())
+        )
+    }
+}
+
+impl Db {
+    /// Drops tombstoned files with everything indexed for them and terms
+    /// left without postings, merges the full-text segments and rebuilds the
+    /// database file without its free pages.
+    pub fn compact(&self) -> CompactReport {
+        let mut report = CompactReport {
+            bytes_before: self.size_on_disk(),
+            ..Default::default()
+        };
+
+        self.pool.execute("BEGIN IMMEDIATE;").unwrap();
+        report.files = self.remove_files("deleted=1", &[]);
+
+        let query = "
+	CREATE TEMP TABLE IF NOT EXISTS unused_term(term_id INTEGER PRIMARY KEY);
+	DELETE FROM unused_term;
+	INSERT INTO unused_term SELECT rowid FROM term WHERE rowid NOT IN (SELECT term_id FROM posting);
+	";
+        self.pool.execute(query).unwrap();
+        report.terms = self.pool.change_count() as i64;
+        let query = "
+	DELETE FROM term_gram WHERE term_id IN (SELECT term_id FROM unused_term);
+	DELETE FROM term WHERE rowid IN (SELECT term_id FROM unused_term);
+	";
+        self.pool.execute(query).unwrap();
+        self.pool.execute("COMMIT;").unwrap();
+
+        self.pool
+            .execute("INSERT INTO file_fts(file_fts) VALUES('optimize');")
+            .unwrap();
+        // can't run in a transaction, copies the whole database
+        self.pool.execute("VACUUM;").unwrap();
+
+        report.bytes_after = self.size_on_disk();
+        report
+    }
+    /// Size of the database file in bytes.
+    pub fn size_on_disk(&self) -> i64 {
+        let pragma = |name: &str| {
+            self.pool
+                .prepare(format!("PRAGMA {};", name))
+                .unwrap()
+                .into_iter()
+                .map(|r| r.unwrap())
+                .next()
+                .map(|r| r.read::<i64, _>(0))
+                .unwrap_or(0)
+        };
+
+        pragma("page_count") * pragma("page_size")
+    }
+}
+
+/// `1536` -> `1.5 KB`.
+pub fn human_size(bytes: i64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KB", "MB", "GB"] {
+        if size.abs() < 1024.0 || unit == "GB" {
+            return if unit == "B" {
+                format!("{} {}", bytes, unit)
+            } else {
+                format!("{:.1} {}", size, unit)
+            };
+        }
+        size /= 1024.0;
+    }
+    unreachable!()
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File, TryLockError};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Overrides the index folder, wins over the config file.
+pub const DATA_DIR_ENV: &str = "BOOK_WORM_DATA_DIR";
+/// Overrides where the config file is read from.
+pub const CONFIG_ENV: &str = "BOOK_WORM_CONFIG";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Folder holding the database and the instance lock.
+    pub data_dir: PathBuf,
+}
+
+impl Config {
+    /// Picks the index folder from, in order: the `--data-dir` flag, the
+    /// environment, `data_dir = ...` in the config file, the platform data
+    /// folder (`$XDG_DATA_HOME/book_worm` on Linux).
+    fn resolve(cli_data_dir: Option<PathBuf>) -> Self {
+        let data_dir = cli_data_dir
+            .or_else(|| env::var_os(DATA_DIR_ENV).map(PathBuf::from))
+            .or_else(|| read_config_file().remove("data_dir").map(PathBuf::from))
+            .unwrap_or_else(default_data_dir);
+
+        Self { data_dir }
+    }
+    pub fn database(&self) -> PathBuf {
+        self.data_dir.join("database.db")
+    }
+    /// Takes the lock file in the data folder so a second instance can't
+    /// write to the same index. The lock is released when the app exits.
+    pub fn lock(&self) -> Result<InstanceLock, String> {
+        fs::create_dir_all(&self.data_dir)
+            .map_err(|e| format!("Can't create {}: {}", self.data_dir.display(), e))?;
+        let path = self.data_dir.join("book_worm.lock");
+        let file =
+            File::create(&path).map_err(|e| format!("Can't open {}: {}", path.display(), e))?;
+
+        match file.try_lock() {
+            Ok(()) => Ok(InstanceLock { _file: file }),
+            Err(TryLockError::WouldBlock) => Err(format!(
+                "Another Book Worm is already using {}",
+                self.data_dir.display()
+            )),
+            Err(TryLockError::Error(e)) => Err(format!("Can't lock {}: {}", path.display(), e)),
+        }
+    }
+}
+
+/// Keeps the data folder locked while alive.
+pub struct InstanceLock {
+    _file: File,
+}
+
+/// Settles the configuration once at startup.
+pub fn init(cli_data_dir: Option<PathBuf>) -> &'static Config {
+    CONFIG.get_or_init(|| Config::resolve(cli_data_dir))
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| Config::resolve(None))
+}
+
+pub fn config_file() -> Option<PathBuf> {
+    env::var_os(CONFIG_ENV)
+        .map(PathBuf::from)
+        .or_else(|| dirs::config_dir().map(|d| d.join("book_worm").join("config")))
+}
+
+/// `key = value` lines, `#` starts a comment.
+fn read_config_file() -> HashMap<String, String> {
+    let content = config_file()
+        .and_then(|p| fs::read_to_string(p).ok())
+        .unwrap_or_default();
+
+    content
+        .lines()
+        .map(|l| l.split('#').next().unwrap_or_default())
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect()
+}
+
+fn default_data_dir() -> PathBuf {
+    dirs::data_dir()
+        .map(|d| d.join("book_worm"))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
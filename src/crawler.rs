@@ -1,45 +1,184 @@
 use crate::db::Dir;
-use std::fs;
+use crate::filetype;
+use std::collections::HashSet;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::Path;
+#[cfg(not(unix))]
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
 
 #[derive(Debug)]
 pub struct DbFileWithoutContent {
     pub file_name: String,
     pub file_type: String,
+    pub extension: String,
+    pub mime: String,
     pub path: String,
+    /// Modification time in unix seconds.
+    pub mtime: i64,
 }
-fn get_type(file_name: String) -> String {
-    let collection: Vec<&str> = file_name.split(".").collect();
-    let idx = collection.len() - 1;
-    String::from(collection[idx])
-}
-pub fn analyze(path: &str) -> (Vec<Dir>, Vec<DbFileWithoutContent>) {
-    let dir = fs::read_dir(path).unwrap();
-
-    let mut dirs: Vec<Dir> = vec![];
-    let mut files: Vec<DbFileWithoutContent> = vec![];
-    for entry in dir {
-        let ent = entry.unwrap();
-        let file_name = ent.file_name().to_str().unwrap().to_string();
-        let file_type = get_type(file_name.clone());
-        let path = ent.path().to_str().unwrap().to_string();
-        let is_dir = ent.file_type().unwrap().is_dir();
-
-        if is_dir {
-            let dir = Dir {
-                name: file_name,
-                path,
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    Follow,
+    Skip,
+}
+
+#[derive(Debug, Clone)]
+pub struct CrawlPolicy {
+    pub symlinks: SymlinkPolicy,
+    /// Do not descend into folders that live on another device (mounts).
+    pub same_filesystem: bool,
+    /// Index a file reachable through several hardlinks only once.
+    pub dedupe_hardlinks: bool,
+}
+impl Default for CrawlPolicy {
+    fn default() -> Self {
+        Self {
+            symlinks: SymlinkPolicy::Skip,
+            same_filesystem: false,
+            dedupe_hardlinks: true,
+        }
+    }
+}
+
+/// Identity of a file on disk. Unix gives us device + inode, elsewhere the
+/// canonical path is the best we have.
+#[cfg(unix)]
+type FileId = (u64, u64);
+#[cfg(not(unix))]
+type FileId = PathBuf;
+
+#[cfg(unix)]
+fn file_id(_path: &Path, meta: &Metadata) -> FileId {
+    use std::os::unix::fs::MetadataExt;
+    (meta.dev(), meta.ino())
+}
+#[cfg(not(unix))]
+fn file_id(path: &Path, _meta: &Metadata) -> FileId {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(unix)]
+fn device(meta: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.dev())
+}
+#[cfg(not(unix))]
+fn device(_meta: &Metadata) -> Option<u64> {
+    None
+}
+
+fn modified(meta: &Metadata) -> i64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// What the index keeps about a file before its text is extracted.
+pub fn describe(path: &Path, meta: &Metadata) -> DbFileWithoutContent {
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default()
+        .to_string();
+    let extension = filetype::extension(&file_name);
+    let detected = filetype::detect(path, &extension);
+
+    DbFileWithoutContent {
+        file_name,
+        file_type: detected.kind,
+        extension,
+        mime: detected.mime,
+        path: path.to_str().unwrap().to_string(),
+        mtime: modified(meta),
+    }
+}
+
+/// Walks a library root one folder at a time, remembering what it has already
+/// seen so symlink cycles, bind mounts and hardlinks are visited once.
+pub struct Crawler {
+    policy: CrawlPolicy,
+    root_dev: Option<u64>,
+    visited_dirs: HashSet<FileId>,
+    seen_files: HashSet<FileId>,
+}
+
+impl Crawler {
+    pub fn new(root: &str, policy: &CrawlPolicy) -> Self {
+        let mut crawler = Self {
+            policy: policy.clone(),
+            root_dev: None,
+            visited_dirs: HashSet::new(),
+            seen_files: HashSet::new(),
+        };
+
+        if let Ok(meta) = fs::metadata(root) {
+            crawler.root_dev = device(&meta);
+            crawler.visited_dirs.insert(file_id(Path::new(root), &meta));
+        }
+
+        crawler
+    }
+    /// Folders and files right inside `path`, and how many of its entries
+    /// couldn't be read or have a name that isn't valid UTF-8, those are
+    /// left out.
+    pub fn analyze(
+        &mut self,
+        path: &str,
+    ) -> io::Result<(Vec<Dir>, Vec<DbFileWithoutContent>, i64)> {
+        let dir = fs::read_dir(path)?;
+
+        let mut dirs: Vec<Dir> = vec![];
+        let mut files: Vec<DbFileWithoutContent> = vec![];
+        let mut unreadable = 0;
+        for entry in dir {
+            let read = entry.ok().and_then(|ent| {
+                let file_type = ent.file_type().ok()?;
+                let file_name = ent.file_name().into_string().ok()?;
+                Some((file_type, file_name, ent.path()))
+            });
+            let Some((file_type, file_name, path)) = read else {
+                unreadable += 1;
+                continue;
             };
-            dirs.push(dir);
-        } else {
-            let file = DbFileWithoutContent {
-                file_name,
-                file_type,
-                path,
+
+            let is_symlink = file_type.is_symlink();
+            if is_symlink && self.policy.symlinks == SymlinkPolicy::Skip {
+                continue;
+            }
+            // follows the link when it is one, a dangling link is skipped
+            let meta = match fs::metadata(&path) {
+                Ok(m) => m,
+                Err(_) => continue,
             };
+            let id = file_id(&path, &meta);
 
-            files.push(file);
+            if meta.is_dir() {
+                if self.policy.same_filesystem && device(&meta) != self.root_dev {
+                    continue;
+                }
+                if !self.visited_dirs.insert(id) {
+                    continue;
+                }
+
+                let dir = Dir {
+                    name: file_name,
+                    path: path.to_string_lossy().to_string(),
+                };
+                dirs.push(dir);
+            } else {
+                if self.policy.dedupe_hardlinks && !self.seen_files.insert(id) {
+                    continue;
+                }
+
+                files.push(describe(&path, &meta));
+            }
         }
-    }
 
-    (dirs, files)
+        Ok((dirs, files, unreadable))
+    }
 }
@@ -1,19 +1,13 @@
-use std::env;
-use std::{
-    fs::{self, File, OpenOptions},
-    io::Write,
-    path::{Path, PathBuf},
-};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 
-use iced::futures::io;
-use regex::Regex;
-use sqlite::{Connection, Value};
-use strsim::{jaro, normalized_levenshtein};
+use sqlite::{Connection, Statement};
 
-use crate::{crawler, docx, pdf};
-use crawler::analyze;
+use crate::crawler::{CrawlPolicy, Crawler, DbFileWithoutContent};
+use crate::{config, docx, hash, pdf, schema, text};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Dir {
     pub name: String,
     pub path: String,
@@ -22,307 +16,481 @@ pub struct Dir {
 pub struct DbFile {
     pub file_name: String,
     pub file_type: String,
+    pub extension: String,
+    pub mime: String,
     pub path: String,
     pub content: String,
-    pub id: i64,
+    pub text_hash: String,
+    pub library_id: i64,
+    pub deleted: bool,
+    /// Detected language of the text, empty when unknown.
+    pub language: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DuplicateKind {
+    /// Byte-for-byte the same file.
+    Identical,
+    /// Different files carrying the same text.
+    SameText,
+}
+#[derive(Debug, Clone)]
+pub struct DuplicateGroup {
+    pub kind: DuplicateKind,
+    pub hash: String,
+    pub files: Vec<Dir>,
 }
 
 #[derive(Debug, Clone)]
 pub struct DictWord {
     pub content: String,
-    pub file_idx: i16,
-    pub word_idx: i32,
+    pub file_idx: i64,
+    pub word_idx: i64,
     pub similarity: f32,
+    /// How rare the matched word is, see `Db::idf`. Results are ranked by
+    /// similarity times weight.
+    pub weight: f32,
     pub file_name: String,
     pub file_path: String,
     pub file_content: String,
+    pub file_type: String,
+    pub file_extension: String,
+    pub file_mime: String,
+    pub text_hash: String,
+    /// Paths of other copies of the same text, filled by `collapse_duplicates`.
+    pub duplicates: Vec<String>,
+}
+impl DictWord {
+    /// What results are ranked by.
+    pub fn score(&self) -> f32 {
+        self.similarity * self.weight
+    }
+}
+#[derive(Debug, Default, Clone)]
+pub struct ScanReport {
+    pub indexed: i64,
+    pub skipped: i64,
+    pub removed: i64,
 }
 pub struct Db {
-    pool: Connection,
-    pub files: i64,
-    pub dirs: i16,
+    pub(crate) pool: Connection,
 }
 
 impl Db {
+    /// Opens the database and brings it up to date, done once at startup.
     pub fn new() -> Self {
-        let connection = sqlite::open("database.db").unwrap();
-        let query = "
-	CREATE TABLE IF NOT EXISTS file(file_name VARCHAR(36), file_type VARCHAR(10), path TEXT, content TEXT);
-    CREATE TABLE IF NOT EXISTS dir(dir_name VARCHAR(36), path TEXT)
-	";
+        let db = Self::open();
+        schema::migrate(&db.pool);
+        db.ensure_fts();
+        db.ensure_grams();
+        db.ensure_word_tokenizer();
 
-        connection.execute(query).unwrap();
+        db
+    }
+    /// Opens a database `new` already brought up to date.
+    pub fn open() -> Self {
+        let mut connection = sqlite::open(config::get().database()).unwrap();
+        // a background scan may hold the write lock for a moment
+        connection.set_busy_timeout(5000).unwrap();
 
-        let files = connection
-            .prepare("SELECT count(*) as len FROM file")
+        Self { pool: connection }
+    }
+    pub fn setting(&self, key: &str) -> Option<String> {
+        let mut stat = self
+            .pool
+            .prepare("SELECT value FROM setting WHERE key=?;")
             .unwrap();
+        stat.bind((1, key)).unwrap();
 
-        let mut file_amount = 0;
-        for mut r in files.into_iter().map(|x| x.unwrap()) {
-            let len = r.take("len");
-
-            match len {
-                Value::Integer(l) => {
-                    file_amount = l;
-                }
-                _ => (),
-            };
-        }
-
-        Self {
-            pool: connection,
-            files: file_amount,
-            dirs: 0,
-        }
+        stat.into_iter()
+            .map(|r| r.unwrap())
+            .next()
+            .map(|r| r.read::<&str, _>("value").to_string())
     }
-    pub fn insert_file(&self, file_name: &str, file_type: &str, path: &str, content: &str) {
+    pub fn set_setting(&self, key: &str, value: &str) {
         let query = "
-	INSERT INTO file VALUES(?, ?, ?, ?) RETURNING *;
+	INSERT INTO setting VALUES(?1, ?2) ON CONFLICT(key) DO UPDATE SET value=?2;
 	";
         let mut stat = self.pool.prepare(query).unwrap();
-        let re = Regex::new(r"^[a-zA-Zа-яА-я]+$").unwrap();
-        let f_content = content
-            .split(" ")
-            .filter(|x| re.is_match(x))
-            .into_iter()
-            .collect::<Vec<&str>>()
-            .join(" ");
-
-        stat.bind((1, file_name)).unwrap();
-        stat.bind((2, file_type)).unwrap();
-        stat.bind((3, path)).unwrap();
-        stat.bind((4, f_content.as_str())).unwrap();
+        stat.bind((1, key)).unwrap();
+        stat.bind((2, value)).unwrap();
+        stat.next().expect("Err during saving setting");
+    }
+    pub fn insert_file(
+        &self,
+        library_id: i64,
+        run_id: i64,
+        file: &DbFileWithoutContent,
+        content: &str,
+        f_content: &str,
+    ) -> i64 {
+        let query = "
+	INSERT INTO file(file_name, file_type, extension, mime, path, content, content_hash, text_hash, library_id, mtime, seen_run)
+	VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING rowid;
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        let content_hash = hash::content_hash(Path::new(&file.path)).unwrap_or_default();
+
+        stat.bind((1, file.file_name.as_str())).unwrap();
+        stat.bind((2, file.file_type.as_str())).unwrap();
+        stat.bind((3, file.extension.as_str())).unwrap();
+        stat.bind((4, file.mime.as_str())).unwrap();
+        stat.bind((5, file.path.as_str())).unwrap();
+        stat.bind((6, f_content)).unwrap();
+        stat.bind((7, content_hash.as_str())).unwrap();
+        stat.bind((8, hash::text_hash(content).as_str())).unwrap();
+        stat.bind((9, library_id)).unwrap();
+        stat.bind((10, file.mtime)).unwrap();
+        stat.bind((11, run_id)).unwrap();
         stat.next().expect("Err during inserting file");
+
+        stat.read::<i64, _>("rowid").unwrap()
     }
     pub fn get_file_idx(&self, file_idx: i64) -> DbFile {
-        let stat = self
+        let mut stat = self
             .pool
             .prepare("SELECT *, rowid FROM file WHERE rowid=?;")
-            .unwrap()
-            .into_iter()
-            .bind((1, file_idx))
             .unwrap();
+        stat.bind((1, file_idx)).unwrap();
+        stat.next().unwrap();
 
-        let mut file_name = String::new();
-        let mut file_type = String::new();
-        let mut path = String::new();
-        let mut content = String::new();
-        let mut id = 0;
-
-        for r in stat.into_iter().map(|r| r.unwrap()) {
-            file_name.push_str(r.read::<&str, _>("file_name"));
-            file_type.push_str(r.read::<&str, _>("file_type"));
-            path.push_str(r.read::<&str, _>("path"));
-            content.push_str(r.read::<&str, _>("content"));
-            id = r.read::<i64, _>("rowid");
-        }
-        DbFile {
-            file_name,
-            file_type,
-            path,
-            content,
-            id,
-        }
+        read_file(&stat)
+    }
+    pub fn insert_dir(&self, library_id: i64, dir_name: &str, path: &str) {
+        let query = "
+	INSERT INTO dir SELECT ?1, ?2, ?3
+	WHERE NOT EXISTS (SELECT 1 FROM dir WHERE path=?2 AND library_id=?3);
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind((1, dir_name)).unwrap();
+        stat.bind((2, path)).unwrap();
+        stat.bind((3, library_id)).unwrap();
+        stat.next().expect("Err during inserting dir");
     }
-    pub fn get_file(&self, file_name: &str) -> DbFile {
-        let stat = self
+    /// Live copy of a file indexed from `path`, with its modification time and
+    /// the last scan run that saw it.
+    fn find_file(&self, library_id: i64, path: &str) -> Option<(i64, i64, i64)> {
+        let query = "
+	SELECT rowid, mtime, IFNULL(seen_run, 0) AS seen_run FROM file WHERE library_id=? AND path=? AND deleted=0;
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind((1, library_id)).unwrap();
+        stat.bind((2, path)).unwrap();
+
+        stat.into_iter().map(|r| r.unwrap()).next().map(|r| {
+            (
+                r.read::<i64, _>("rowid"),
+                r.read::<i64, _>("mtime"),
+                r.read::<i64, _>("seen_run"),
+            )
+        })
+    }
+    fn mark_seen(&self, file_idx: i64, run_id: i64) {
+        let mut stat = self
             .pool
-            .prepare("SELECT *, rowid FROM file WHERE file_name=?;")
-            .unwrap()
-            .into_iter()
-            .bind((1, file_name))
+            .prepare("UPDATE file SET seen_run=? WHERE rowid=?;")
+            .unwrap();
+        stat.bind((1, run_id)).unwrap();
+        stat.bind((2, file_idx)).unwrap();
+        stat.next().unwrap();
+    }
+    fn has_author(&self, file_idx: i64) -> bool {
+        let mut stat = self
+            .pool
+            .prepare("SELECT author IS NOT NULL AS known FROM file WHERE rowid=?;")
             .unwrap();
+        stat.bind((1, file_idx)).unwrap();
+        stat.next().unwrap();
 
-        let mut file_name = String::new();
-        let mut file_type = String::new();
-        let mut path = String::new();
-        let mut content = String::new();
-        let mut id = 0;
-
-        for r in stat.into_iter().map(|r| r.unwrap()) {
-            file_name.push_str(r.read::<&str, _>("file_name"));
-            file_type.push_str(r.read::<&str, _>("file_type"));
-            path.push_str(r.read::<&str, _>("path"));
-            content.push_str(r.read::<&str, _>("content"));
-            id = r.read::<i64, _>("rowid");
-        }
-        DbFile {
-            file_name,
-            file_type,
-            path,
-            content,
-            id,
-        }
+        stat.read::<i64, _>("known").unwrap() == 1
     }
-    pub fn insert_dir(&self, dir_name: &str, path: &str) {
+    fn set_author(&self, file_idx: i64, author: &str) {
+        let mut stat = self
+            .pool
+            .prepare("UPDATE file SET author=? WHERE rowid=?;")
+            .unwrap();
+        stat.bind((1, author)).unwrap();
+        stat.bind((2, file_idx)).unwrap();
+        stat.next().unwrap();
+    }
+    /// Marks an indexed copy of a file as outdated or gone from disk. Its
+    /// postings stay in the index until it is compacted, searches skip it.
+    pub(crate) fn tombstone(&self, file_idx: i64) {
+        let mut stat = self
+            .pool
+            .prepare("UPDATE file SET deleted=1 WHERE rowid=?;")
+            .unwrap();
+        stat.bind((1, file_idx)).unwrap();
+        stat.next().unwrap();
+    }
+    /// Incremental scan of a root: unchanged files are skipped, changed ones
+    /// re-indexed and files that disappeared from under the root tombstoned.
+    ///
+    /// Every file is indexed in its own transaction and the progress of the
+    /// run is saved with it, so an interrupted scan leaves no half-indexed
+    /// file behind and going on with the same `run_id` skips what was done.
+    pub fn scan(
+        &self,
+        library_id: i64,
+        path: &str,
+        policy: &CrawlPolicy,
+        run_id: i64,
+        report: &mut ScanReport,
+    ) {
+        // a root that can't be listed fails the run, its files are kept
+        fs::read_dir(path).expect("Err during reading root");
+        let mut crawler = Crawler::new(path, policy);
+        let options = self.word_tokenizer();
+
+        self.scan_dir(library_id, run_id, &mut crawler, path, &options, report);
+
         let query = "
-	INSERT INTO dir VALUES(?, ?) RETURNING *;
+	UPDATE file SET deleted=1
+	WHERE library_id=? AND deleted=0 AND IFNULL(seen_run, 0)!=? AND substr(path, 1, length(?3))=?3;
 	";
+        let prefix = Path::new(path).join("");
+        self.pool.execute("BEGIN IMMEDIATE;").unwrap();
         let mut stat = self.pool.prepare(query).unwrap();
-        stat.bind((1, dir_name)).unwrap();
-        stat.bind((2, path)).unwrap();
-        stat.next().expect("Err during inserting file");
+        stat.bind((1, library_id)).unwrap();
+        stat.bind((2, run_id)).unwrap();
+        stat.bind((3, prefix.to_str().unwrap())).unwrap();
+        stat.next().unwrap();
+        report.removed += self.pool.change_count() as i64;
+        self.fts_purge();
+        self.pool.execute("COMMIT;").unwrap();
     }
-    pub fn scan(&self, path: &str) -> &Self {
-        let (dirs, files) = analyze(path);
+    fn scan_dir(
+        &self,
+        library_id: i64,
+        run_id: i64,
+        crawler: &mut Crawler,
+        path: &str,
+        options: &text::TokenizerOptions,
+        report: &mut ScanReport,
+    ) {
+        let (dirs, files, unreadable) = match crawler.analyze(path) {
+            Ok(found) => found,
+            // a folder that can't be listed, its files count as gone
+            Err(_) => {
+                report.skipped += 1;
+                return;
+            }
+        };
+        report.skipped += unreadable;
 
         for dir in dirs {
-            self.insert_dir(&dir.name, &dir.path);
+            self.insert_dir(library_id, &dir.name, &dir.path);
 
-            self.scan(&dir.path);
+            self.scan_dir(library_id, run_id, crawler, &dir.path, options, report);
         }
+
+        let mut unchanged = vec![];
         for file in files {
-            let content = match file.file_type.as_str() {
-                "pdf" => pdf::get(&file.path).unwrap(),
-                "docx" => docx::get(&file.path).unwrap(),
-                _ => "".to_string(),
+            let old = match self.find_file(library_id, &file.path) {
+                // done before the run was interrupted
+                Some((_, _, seen_run)) if seen_run == run_id => continue,
+                Some((id, mtime, _)) if mtime == file.mtime => {
+                    unchanged.push((id, file));
+                    continue;
+                }
+                Some((id, _, _)) => Some(id),
+                None => None,
             };
 
-            self.insert_file(&file.file_name, &file.file_type, &file.path, &content);
-
-            let db_file = self.get_file(&file.file_name);
-            let path = env::current_dir().unwrap();
-            let current_path = Path::new(path.as_os_str()).join("dict");
-            let re = Regex::new(r"^[a-zA-Zа-яА-я]+$").unwrap();
-
-            // create dict if doesnt exist
-            fs::create_dir("dict").ok();
-            for (idx, w) in db_file
-                .content
-                .split(" ")
-                .filter(|x| re.is_match(x))
-                .enumerate()
-            {
-                self.insert_word(&db_file.id, idx, w, &current_path.as_path());
+            // extracting is slow, the write lock is only taken for the inserts
+            let (content, author) = extract(&file);
+
+            self.pool.execute("BEGIN IMMEDIATE;").unwrap();
+            if let Some(id) = old {
+                self.tombstone(id);
             }
+            self.index_file(
+                library_id,
+                run_id,
+                &file,
+                content.as_deref(),
+                &author,
+                options,
+            );
+            report.indexed += 1;
+            self.save_progress(run_id, report);
+            self.pool.execute("COMMIT;").unwrap();
         }
 
-        let entries = fs::read_dir("dict")
-            .unwrap()
-            .map(|res| res.map(|e| e.path()))
-            .collect::<Result<Vec<_>, io::Error>>()
-            .unwrap();
-
-        for path in entries {
-            self.sort_file(&path);
+        if !unchanged.is_empty() {
+            // files indexed before authors were kept
+            let authors: Vec<(i64, String)> = unchanged
+                .iter()
+                .filter(|(id, _)| !self.has_author(*id))
+                .map(|(id, file)| (*id, author(file)))
+                .collect();
+
+            self.pool.execute("BEGIN IMMEDIATE;").unwrap();
+            for (id, author) in authors {
+                self.set_author(id, &author);
+            }
+            for (id, _) in unchanged {
+                self.mark_seen(id, run_id);
+                report.skipped += 1;
+            }
+            self.save_progress(run_id, report);
+            self.pool.execute("COMMIT;").unwrap();
         }
-        //
-
-        self
     }
-    fn write_file_stream(&self, file_path: &PathBuf, append: bool) -> File {
-        if append {
-            let file = OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(file_path)
-                .unwrap();
-
-            file
-        } else {
-            let file = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(file_path)
-                .unwrap();
-
-            file
-        }
+    /// Indexes the text of a file, inside the caller's transaction that
+    /// tombstoned its old copy. `None` is a file the text couldn't be read
+    /// from, `options` are read once for a whole scan.
+    pub(crate) fn index_file(
+        &self,
+        library_id: i64,
+        run_id: i64,
+        file: &DbFileWithoutContent,
+        content: Option<&str>,
+        author: &str,
+        options: &text::TokenizerOptions,
+    ) {
+        let failed = content.is_none();
+        let content = content.unwrap_or_default();
+
+        let tokens = text::tokens(content, options);
+        let f_content = text::filtered(&tokens);
+        let language = text::detect_language(content);
+        let id = self.insert_file(library_id, run_id, file, content, &f_content);
+
+        let mut stat = self
+            .pool
+            .prepare("UPDATE file SET extract_failed=?, language=?, author=? WHERE rowid=?;")
+            .unwrap();
+        stat.bind((1, failed as i64)).unwrap();
+        stat.bind((2, language.as_str())).unwrap();
+        stat.bind((3, author)).unwrap();
+        stat.bind((4, id)).unwrap();
+        stat.next().unwrap();
+
+        self.insert_text(id, content);
+        self.fts_insert(id, &f_content);
+        self.insert_words(id, &tokens, &language, options);
     }
-    fn insert_word(&self, file_idx: &i64, idx: usize, word: &str, path: &Path) -> usize {
-        let char = &word.chars().take(1).next().unwrap();
-        let file_path = path.join(char.to_string().as_str());
+    pub fn duplicates(&self) -> Vec<DuplicateGroup> {
+        let identical = "
+	SELECT content_hash AS hash, file_name, path FROM file
+	WHERE content_hash IN (
+		SELECT content_hash FROM file WHERE content_hash != '' AND deleted=0
+		GROUP BY content_hash HAVING count(*) > 1)
+	AND deleted=0
+	ORDER BY content_hash, path;
+	";
+        // same text in files that are not all byte-identical
+        let same_text = "
+	SELECT text_hash AS hash, file_name, path FROM file
+	WHERE text_hash IN (
+		SELECT text_hash FROM file WHERE text_hash != '' AND deleted=0
+		GROUP BY text_hash HAVING count(DISTINCT content_hash) > 1)
+	AND deleted=0
+	ORDER BY text_hash, path;
+	";
 
-        let mut file = self.write_file_stream(&file_path, true);
-        let content = format!("{}|{}|{}\n", word, file_idx, idx);
-        file.write(content.as_bytes()).unwrap()
+        let mut groups = self.read_duplicates(identical, DuplicateKind::Identical);
+        groups.extend(self.read_duplicates(same_text, DuplicateKind::SameText));
+
+        groups
     }
-    fn sort_file(&self, file_path: &PathBuf) {
-        let raw_buff = fs::read(file_path).unwrap();
-        let parsed_buff = String::from_utf8(raw_buff).unwrap();
-        let content: Vec<&str> = parsed_buff.split("\n").collect();
-        let mut file_content = content.clone();
-        file_content.sort();
-        file_content.sort_by(|a, b| a.len().cmp(&b.len()));
-
-        let mut file = self.write_file_stream(file_path, false);
-
-        for line in file_content {
-            let parsed_txt = format!("{}\n", line);
-            file.write(parsed_txt.as_bytes())
-                .expect("Err during writing the file");
+    fn read_duplicates(&self, query: &str, kind: DuplicateKind) -> Vec<DuplicateGroup> {
+        let mut groups: Vec<DuplicateGroup> = vec![];
+
+        for r in self
+            .pool
+            .prepare(query)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap())
+        {
+            let hash = r.read::<&str, _>("hash");
+            let copy = Dir {
+                name: r.read::<&str, _>("file_name").to_string(),
+                path: r.read::<&str, _>("path").to_string(),
+            };
+
+            match groups.last_mut() {
+                Some(group) if group.hash == hash => group.files.push(copy),
+                _ => groups.push(DuplicateGroup {
+                    kind: kind.clone(),
+                    hash: hash.to_string(),
+                    files: vec![copy],
+                }),
+            }
         }
-    }
-    pub fn search_word(
-        &self,
-        word: &str,
-        lensh_k: f64,
-        jer_k: f64,
-    ) -> Result<Vec<DictWord>, io::Error> {
-        let char = &word.chars().take(1).next().unwrap();
-        let path_str = format!("dict/{}", char);
-        let path = Path::new(path_str.as_str());
 
-        let raw_file = fs::read(path)?;
+        groups
+    }
+}
 
-        let raw_file_content = String::from_utf8(raw_file).unwrap();
-        let file_content: Vec<&str> = raw_file_content.split("\n").collect();
+/// Text of a file in a format we can read, empty for the others, and the
+/// author it names, both from one read of the file. The text is `None` when
+/// the file is broken or its format is not understood.
+pub fn extract(file: &DbFileWithoutContent) -> (Option<String>, String) {
+    let read = match file.file_type.as_str() {
+        "pdf" => pdf::get(&file.path),
+        "docx" => docx::get(&file.path),
+        _ => Ok((String::new(), String::new())),
+    };
+
+    match read {
+        Ok((text, author)) => (Some(text), author),
+        Err(()) => (None, String::new()),
+    }
+}
 
-        let mut res: Vec<DictWord> = vec![];
+/// Author a file names in its properties, empty when it names none. For
+/// files indexed before authors were kept, `extract` reads it otherwise.
+pub fn author(file: &DbFileWithoutContent) -> String {
+    match file.file_type.as_str() {
+        "pdf" => pdf::author(&file.path),
+        "docx" => docx::author(&file.path),
+        _ => String::new(),
+    }
+}
 
-        for line in file_content {
-            if line.len() < 1 {
-                continue;
-            };
+fn read_file(r: &Statement) -> DbFile {
+    DbFile {
+        file_name: r.read::<String, _>("file_name").unwrap(),
+        file_type: r.read::<String, _>("file_type").unwrap(),
+        extension: r.read::<String, _>("extension").unwrap(),
+        mime: r.read::<String, _>("mime").unwrap(),
+        path: r.read::<String, _>("path").unwrap(),
+        content: r.read::<String, _>("content").unwrap(),
+        text_hash: r.read::<String, _>("text_hash").unwrap(),
+        library_id: r.read::<i64, _>("library_id").unwrap(),
+        deleted: r.read::<i64, _>("deleted").unwrap() == 1,
+        language: r.read::<String, _>("language").unwrap(),
+    }
+}
 
-            let mut parts = line.split("|");
-            let line_word = parts.next().unwrap();
-            let file_idx = parts.next().unwrap().parse::<i16>().unwrap();
-            let word_idx = parts.next().unwrap().parse::<i32>().unwrap();
-
-            let jer = jaro(word, line_word) * 100.0;
-            let lensh = normalized_levenshtein(word, line_word) * 100.0;
-            let similarity = ((jer + lensh) / 2.0) as f32;
-
-            let common_average = (lensh + jer) / 2.0;
-            let k_average = (lensh_k + jer_k) / 2.0;
-
-            if common_average > k_average {
-                let file = self.get_file_idx((file_idx as i64).clone());
-                let raw_content = file.content.split(" ").collect::<Vec<&str>>();
-                let len_content = raw_content.len();
-                let take_idx = if word_idx + 10 > len_content as i32 {
-                    len_content
-                } else {
-                    (word_idx + 10) as usize
-                };
-
-                let skip = if word_idx > 11 {
-                    word_idx - 10
-                } else {
-                    word_idx
-                } as usize;
-                let take = (take_idx) as usize;
-
-                let file_content = format!(
-                    "...{}...",
-                    raw_content[skip..take].join(" ").replace("\n", " ")
-                );
-
-                let resp_word = DictWord {
-                    content: word.to_string(),
-                    file_idx,
-                    word_idx,
-                    similarity,
-                    file_name: file.file_name,
-                    file_path: file.path,
-                    file_content: file_content.to_string(),
-                };
-
-                res.push(resp_word);
+/// Keeps one hit per place in a text that exists in several copies, the other
+/// copies' paths are moved into `duplicates` of the kept hit.
+pub fn collapse_duplicates(results: Vec<DictWord>) -> Vec<DictWord> {
+    let mut res: Vec<DictWord> = vec![];
+    // the first hit at each place, copies of it all come from other files
+    let mut kept: HashMap<(String, i64), usize> = HashMap::new();
+
+    for word in results {
+        if word.text_hash.is_empty() {
+            res.push(word);
+            continue;
+        }
+        let key = (word.text_hash.clone(), word.word_idx);
+        match kept.get(&key).map(|&idx| &mut res[idx]) {
+            Some(first) if first.file_idx != word.file_idx => {
+                if !first.duplicates.contains(&word.file_path) {
+                    first.duplicates.push(word.file_path);
+                }
+            }
+            _ => {
+                kept.entry(key).or_insert(res.len());
+                res.push(word);
             }
         }
-
-        Ok(res)
     }
+
+    res
 }
@@ -1,16 +1,19 @@
+use std::io::{Cursor, Read};
+
 use docx_rs::*;
+use regex::Regex;
 
 #[derive(Debug)]
 enum ParsedDoc {
     Paragraph(Option<String>),
-    Table(DocxTable),
+    Table(Box<DocxTable>),
 }
 fn parse_doc(child: DocumentChild) -> Option<ParsedDoc> {
     match child {
         DocumentChild::Paragraph(child) => {
             let list = child.clone().children;
 
-            if list.len() > 0 {
+            if !list.is_empty() {
                 let resp = get_runner(list[0].clone());
 
                 Some(ParsedDoc::Paragraph(resp))
@@ -23,7 +26,7 @@ fn parse_doc(child: DocumentChild) -> Option<ParsedDoc> {
             table.scan_table();
             table.scan_rows();
 
-            Some(ParsedDoc::Table(table))
+            Some(ParsedDoc::Table(Box::new(table)))
         }
         _ => None,
     }
@@ -36,19 +39,14 @@ fn get_runner(paragraph: ParagraphChild) -> Option<String> {
         ParagraphChild::Run(rnrs) => {
             let rnr_children = rnrs.clone().children;
 
-            if rnr_children.len() > 0 {
+            if !rnr_children.is_empty() {
                 let parsed = parse_runner(rnr_children[0].clone());
 
-                match parsed {
-                    Some(txt) => match txt {
-                        ParsedRunner::Text(txt) => {
-                            res.push_str(&txt.text);
-                        }
-                    },
-                    None => (),
+                if let Some(ParsedRunner::Text(txt)) = parsed {
+                    res.push_str(&txt.text);
                 }
             } else {
-                res.push_str("\n");
+                res.push('\n');
             }
             Some(res)
         }
@@ -112,19 +110,13 @@ impl DocxTable {
                     TableRowChild::TableCell(c) => {
                         let content = c.children;
                         for co in content {
-                            match co {
-                                TableCellContent::Paragraph(p) => {
-                                    let list = p.children;
-                                    if list.len() > 0 {
-                                        let rnr = get_runner(list[0].clone());
-
-                                        match rnr {
-                                            Some(r) => cell.text = r,
-                                            _ => (),
-                                        }
+                            if let TableCellContent::Paragraph(p) = co {
+                                let list = p.children;
+                                if !list.is_empty() {
+                                    if let Some(r) = get_runner(list[0].clone()) {
+                                        cell.text = r;
                                     }
                                 }
-                                _ => (),
                             }
                         }
                     }
@@ -139,7 +131,7 @@ impl DocxTable {
         for r in self.rows.iter() {
             for c in r.cells.iter() {
                 let formated = &format!(" {}", c.text);
-                res.push_str(&formated.as_str());
+                res.push_str(formated.as_str());
             }
         }
 
@@ -147,30 +139,56 @@ impl DocxTable {
     }
 }
 
-pub fn get(file_path: &str) -> Result<String, ()> {
-    let file = std::fs::read(file_path).unwrap();
-    let document = read_docx(file.as_slice()).unwrap().document;
+/// Text of the paragraphs and tables and the author, from one read of the
+/// file.
+pub fn get(file_path: &str) -> Result<(String, String), ()> {
+    let file = std::fs::read(file_path).map_err(|_| ())?;
+    let author = read_creator(&file).unwrap_or_default();
+    let document = read_docx(file.as_slice()).map_err(|_| ())?.document;
     let mut res = String::new();
 
     for doc_child in document.children {
-        let parsed_doc = parse_doc(doc_child);
-
-        match parsed_doc {
-            Some(dc) => match dc {
-                ParsedDoc::Paragraph(p) => match p {
-                    Some(txt) => {
-                        let formatted = &format!("{}\n", &txt);
-                        res.push_str(&formatted);
-                    }
-                    _ => (),
-                },
-                ParsedDoc::Table(t) => {
-                    res.push_str(&t.convert());
-                    ()
-                }
-            },
+        match parse_doc(doc_child) {
+            Some(ParsedDoc::Paragraph(Some(txt))) => {
+                let formatted = &format!("{}\n", &txt);
+                res.push_str(formatted);
+            }
+            Some(ParsedDoc::Table(t)) => {
+                res.push_str(&t.convert());
+            }
             _ => (),
         }
     }
-    Ok(res)
+    Ok((res, author))
+}
+
+/// Author from the document properties, empty when there is none.
+pub fn author(file_path: &str) -> String {
+    std::fs::read(file_path)
+        .ok()
+        .and_then(|file| read_creator(&file))
+        .unwrap_or_default()
+}
+
+fn read_creator(file: &[u8]) -> Option<String> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(file)).ok()?;
+    let mut core = String::new();
+    archive
+        .by_name("docProps/core.xml")
+        .ok()?
+        .read_to_string(&mut core)
+        .ok()?;
+
+    let creator = Regex::new(r"<dc:creator>([^<]*)</dc:creator>")
+        .unwrap()
+        .captures(&core)?
+        .get(1)?
+        .as_str()
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+
+    Some(creator.trim().to_string())
 }
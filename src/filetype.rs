@@ -0,0 +1,184 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use zip::ZipArchive;
+
+#[derive(Debug, Clone)]
+pub struct Detected {
+    /// Short name the extractors dispatch on, e.g. `pdf` or `docx`.
+    pub kind: String,
+    pub mime: String,
+}
+
+impl Detected {
+    fn new(kind: &str, mime: &str) -> Self {
+        Self {
+            kind: kind.to_string(),
+            mime: mime.to_string(),
+        }
+    }
+}
+
+/// Lowercased extension after the last dot, empty for `README` or `.bashrc`.
+pub fn extension(file_name: &str) -> String {
+    match file_name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => ext.to_lowercase(),
+        _ => String::new(),
+    }
+}
+
+/// Whether a file's extension names another format than the one detected.
+/// A file without one names none, spellings like `jpeg` and `jpg` name the
+/// same.
+pub fn misnamed(extension: &str, kind: &str) -> bool {
+    !extension.is_empty() && same_kind(extension) != same_kind(kind)
+}
+
+fn same_kind(extension: &str) -> &str {
+    match extension {
+        "jpeg" | "jpe" | "jfif" => "jpg",
+        "htm" => "html",
+        "tif" => "tiff",
+        "dot" => "doc",
+        "docm" | "dotx" => "docx",
+        "xlt" => "xls",
+        "xlsm" => "xlsx",
+        "pps" | "pot" => "ppt",
+        "pptm" | "ppsx" => "pptx",
+        e => e,
+    }
+}
+
+/// Extensions of formats that are plain text underneath, kept as the kind of
+/// text content. Text under any other extension is `txt`.
+const TEXT_FORMATS: [&str; 16] = [
+    "txt", "text", "md", "markdown", "rst", "csv", "tsv", "log", "json", "xml", "html", "htm",
+    "yaml", "yml", "toml", "ini",
+];
+
+/// Works out the real format of a file from its first bytes, looking inside
+/// zip containers for office documents. Unrecognised content keeps the
+/// declared extension as its kind.
+pub fn detect(path: &Path, extension: &str) -> Detected {
+    let mut head = [0u8; 512];
+    let read = match File::open(path).and_then(|mut f| f.read(&mut head)) {
+        Ok(n) => n,
+        Err(_) => return by_extension(extension),
+    };
+    let head = &head[..read];
+
+    if head.starts_with(b"%PDF-") {
+        Detected::new("pdf", "application/pdf")
+    } else if head.starts_with(b"PK\x03\x04") {
+        inspect_zip(path)
+    } else if head.starts_with(b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1") {
+        inspect_ole(extension)
+    } else if head.starts_with(b"{\\rtf") {
+        Detected::new("rtf", "application/rtf")
+    } else if head.starts_with(b"\x89PNG") {
+        Detected::new("png", "image/png")
+    } else if head.starts_with(b"\xFF\xD8\xFF") {
+        Detected::new("jpg", "image/jpeg")
+    } else if head.starts_with(b"GIF8") {
+        Detected::new("gif", "image/gif")
+    } else if looks_like_text(head) {
+        let kind = if TEXT_FORMATS.contains(&extension) {
+            extension
+        } else {
+            "txt"
+        };
+        Detected::new(kind, "text/plain")
+    } else {
+        by_extension(extension)
+    }
+}
+
+fn inspect_zip(path: &Path) -> Detected {
+    let archive = File::open(path).ok().and_then(|f| ZipArchive::new(f).ok());
+    let archive = match archive {
+        Some(a) => a,
+        None => return Detected::new("zip", "application/zip"),
+    };
+    let has = |name: &str| archive.file_names().any(|n| n == name);
+
+    if has("word/document.xml") {
+        Detected::new(
+            "docx",
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        )
+    } else if has("xl/workbook.xml") {
+        Detected::new(
+            "xlsx",
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        )
+    } else if has("ppt/presentation.xml") {
+        Detected::new(
+            "pptx",
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        )
+    } else if has("META-INF/container.xml") && has("mimetype") {
+        Detected::new("epub", "application/epub+zip")
+    } else {
+        Detected::new("zip", "application/zip")
+    }
+}
+
+/// Old Office formats and Outlook messages share one container, the
+/// extension tells them apart.
+fn inspect_ole(extension: &str) -> Detected {
+    match extension {
+        "doc" | "dot" => Detected::new("doc", "application/msword"),
+        "xls" | "xlt" => Detected::new("xls", "application/vnd.ms-excel"),
+        "ppt" | "pps" | "pot" => Detected::new("ppt", "application/vnd.ms-powerpoint"),
+        "msg" => Detected::new("msg", "application/vnd.ms-outlook"),
+        _ => Detected::new("ole", "application/x-ole-storage"),
+    }
+}
+
+fn looks_like_text(head: &[u8]) -> bool {
+    if head.is_empty() || head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        // the buffer may cut a multibyte character in half
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+fn by_extension(extension: &str) -> Detected {
+    Detected::new(extension, "application/octet-stream")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect_text(name: &str) -> Detected {
+        let path = std::env::temp_dir().join(format!("filetype-{}-{name}", std::process::id()));
+        std::fs::write(&path, "just some notes\n").unwrap();
+        let detected = detect(&path, &extension(name));
+        std::fs::remove_file(&path).unwrap();
+        detected
+    }
+
+    #[test]
+    fn text_keeps_text_extensions() {
+        for name in ["notes.txt", "notes.md", "table.csv", "page.htm"] {
+            let detected = detect_text(name);
+            assert_eq!(detected.kind, extension(name));
+            assert_eq!(detected.mime, "text/plain");
+            assert!(!misnamed(&extension(name), &detected.kind));
+        }
+    }
+
+    #[test]
+    fn text_under_other_extensions() {
+        for name in ["notes.pdf", "notes.docx", "notes.jpg", "README"] {
+            let detected = detect_text(name);
+            assert_eq!(detected.kind, "txt");
+            assert_eq!(misnamed(&extension(name), &detected.kind), name != "README");
+        }
+    }
+}
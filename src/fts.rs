@@ -0,0 +1,121 @@
+use crate::db::{Db, DictWord};
+
+/// Tokenizers the full-text table can be built with. The first is the default.
+pub const TOKENIZERS: [&str; 4] = [
+    "unicode61 remove_diacritics 2",
+    "porter unicode61 remove_diacritics 2",
+    "trigram",
+    "ascii",
+];
+
+impl Db {
+    pub fn tokenizer(&self) -> String {
+        self.setting("fts_tokenizer")
+            .filter(|t| TOKENIZERS.contains(&t.as_str()))
+            .unwrap_or(TOKENIZERS[0].to_string())
+    }
+    /// Creates the full-text table when it is missing and fills it from the
+    /// live files.
+    pub(crate) fn ensure_fts(&self) {
+        let exists = self
+            .pool
+            .prepare("SELECT 1 FROM sqlite_master WHERE name='file_fts';")
+            .unwrap()
+            .into_iter()
+            .next()
+            .is_some();
+        if exists {
+            return;
+        }
+
+        // the tokenizer can't be bound, it is one of TOKENIZERS
+        let query = format!(
+            "
+	CREATE VIRTUAL TABLE file_fts USING fts5(content, tokenize='{}');
+	INSERT INTO file_fts(rowid, content) SELECT rowid, content FROM file WHERE deleted=0;
+	",
+            self.tokenizer()
+        );
+        self.pool.execute(query).unwrap();
+    }
+    /// Rebuilds the full-text table with another tokenizer.
+    pub fn set_tokenizer(&self, tokenizer: &str) {
+        if !TOKENIZERS.contains(&tokenizer) {
+            return;
+        }
+        self.set_setting("fts_tokenizer", tokenizer);
+        self.pool.execute("DROP TABLE IF EXISTS file_fts;").unwrap();
+        self.ensure_fts();
+    }
+    pub(crate) fn fts_insert(&self, file_idx: i64, content: &str) {
+        let mut stat = self
+            .pool
+            .prepare("INSERT INTO file_fts(rowid, content) VALUES(?, ?);")
+            .unwrap();
+        stat.bind((1, file_idx)).unwrap();
+        stat.bind((2, content)).unwrap();
+        stat.next().expect("Err during inserting file text");
+    }
+    /// Drops tombstoned files from the full-text table.
+    pub(crate) fn fts_purge(&self) {
+        let query = "
+	DELETE FROM file_fts WHERE rowid IN (SELECT rowid FROM file WHERE deleted=1);
+	";
+        self.pool.execute(query).unwrap();
+    }
+    /// Full-text search with the FTS5 query syntax (`word`, `"a phrase"`,
+    /// `pref*`, `a OR b`), best matches first. `language` keeps files
+    /// detected to be in that language only.
+    pub fn search_fts(
+        &self,
+        query: &str,
+        libraries: &[i64],
+        language: Option<&str>,
+    ) -> Result<Vec<DictWord>, String> {
+        let in_libraries = vec!["?"; libraries.len()].join(", ");
+        let sql = format!(
+            "
+	SELECT file.rowid AS rowid, file_name, path, file_type, extension, mime, text_hash,
+		bm25(file_fts) AS rank, snippet(file_fts, 0, '[', ']', '...', 12) AS snip
+	FROM file_fts JOIN file ON file.rowid = file_fts.rowid
+	WHERE file_fts MATCH ? AND file.deleted=0 AND file.library_id IN ({}) AND file.language LIKE ?
+	ORDER BY rank LIMIT 200;
+	",
+            in_libraries
+        );
+
+        let mut stat = self.pool.prepare(sql).map_err(|e| e.to_string())?;
+        stat.bind((1, query)).map_err(|e| e.to_string())?;
+        for (i, library) in libraries.iter().enumerate() {
+            stat.bind((i + 2, *library)).map_err(|e| e.to_string())?;
+        }
+        stat.bind((libraries.len() + 2, language.unwrap_or("%")))
+            .map_err(|e| e.to_string())?;
+
+        let mut res: Vec<DictWord> = vec![];
+        for r in stat.into_iter() {
+            // a malformed query only fails once it runs
+            let r = r.map_err(|e| e.to_string())?;
+
+            res.push(DictWord {
+                content: query.to_string(),
+                file_idx: r.read::<i64, _>("rowid"),
+                word_idx: 0,
+                // bm25 is lower for better matches
+                similarity: -r.read::<f64, _>("rank") as f32,
+                // bm25 weighs rare words already
+                weight: 1.0,
+                file_name: r.read::<&str, _>("file_name").to_string(),
+                file_path: r.read::<&str, _>("path").to_string(),
+                file_content: r.read::<&str, _>("snip").to_string(),
+                file_type: r.read::<&str, _>("file_type").to_string(),
+                file_extension: r.read::<&str, _>("extension").to_string(),
+                file_mime: r.read::<&str, _>("mime").to_string(),
+                text_hash: r.read::<&str, _>("text_hash").to_string(),
+                duplicates: vec![],
+            });
+        }
+
+        Ok(res)
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeSet;
+
+use crate::db::Db;
+
+/// Character trigrams of a word padded with `$`, so `rust` gives `$ru`, `rus`,
+/// `ust` and `st$`. A typo only breaks the grams it falls into, wherever it is.
+pub fn trigrams(word: &str) -> Vec<String> {
+    let chars: Vec<char> = format!("${}$", word).chars().collect();
+    let grams: BTreeSet<String> = chars.windows(3).map(|w| w.iter().collect()).collect();
+
+    grams.into_iter().collect()
+}
+
+impl Db {
+    pub(crate) fn insert_term_grams(&self, term_id: i64, term: &str) {
+        let mut stat = self
+            .pool
+            .prepare("INSERT INTO term_gram VALUES(?, ?);")
+            .unwrap();
+
+        for gram in trigrams(term) {
+            stat.reset().unwrap();
+            stat.bind((1, gram.as_str())).unwrap();
+            stat.bind((2, term_id)).unwrap();
+            stat.next().expect("Err during inserting term gram");
+        }
+    }
+    /// Builds grams for terms indexed before the fuzzy index existed.
+    pub(crate) fn ensure_grams(&self) {
+        let has = |query: &str| {
+            self.pool
+                .prepare(query)
+                .unwrap()
+                .into_iter()
+                .next()
+                .is_some()
+        };
+        if !has("SELECT 1 FROM term LIMIT 1;") || has("SELECT 1 FROM term_gram LIMIT 1;") {
+            return;
+        }
+
+        let terms: Vec<(i64, String)> = self
+            .pool
+            .prepare("SELECT rowid, term FROM term;")
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap())
+            .map(|r| {
+                (
+                    r.read::<i64, _>("rowid"),
+                    r.read::<&str, _>("term").to_string(),
+                )
+            })
+            .collect();
+
+        self.pool.execute("BEGIN;").unwrap();
+        for (id, term) in terms {
+            self.insert_term_grams(id, &term);
+        }
+        self.pool.execute("COMMIT;").unwrap();
+    }
+    /// Terms sharing enough trigrams with `word` to be worth comparing, so
+    /// only a small part of the vocabulary is scored.
+    pub(crate) fn fuzzy_terms(&self, word: &str) -> Vec<(i64, String)> {
+        let grams = trigrams(word);
+        if word.is_empty() {
+            return vec![];
+        }
+        // a typo breaks up to three grams, short words keep few of theirs
+        let min_hits = (grams.len() / 4).max(1) as i64;
+
+        let in_grams = vec!["?"; grams.len()].join(", ");
+        let query = format!(
+            "
+	SELECT term.rowid AS rowid, term FROM term JOIN (
+		SELECT term_id, count(*) AS hits FROM term_gram WHERE gram IN ({})
+		GROUP BY term_id HAVING hits >= ?) AS candidate
+	ON term.rowid = candidate.term_id;
+	",
+            in_grams
+        );
+        let mut stat = self.pool.prepare(query).unwrap();
+        for (i, gram) in grams.iter().enumerate() {
+            stat.bind((i + 1, gram.as_str())).unwrap();
+        }
+        stat.bind((grams.len() + 1, min_hits)).unwrap();
+
+        stat.into_iter()
+            .map(|r| r.unwrap())
+            .map(|r| {
+                (
+                    r.read::<i64, _>("rowid"),
+                    r.read::<&str, _>("term").to_string(),
+                )
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,36 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+/// Hash of the raw bytes, equal for byte-identical copies.
+pub fn content_hash(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Hash of the extracted text with case and whitespace normalised, equal for
+/// the same book saved as pdf and docx or re-exported with other metadata.
+/// Empty text gives an empty hash so unparsed files never group together.
+pub fn text_hash(text: &str) -> String {
+    let mut hasher = Sha256::new();
+    let mut empty = true;
+
+    for word in text.split_whitespace() {
+        if !empty {
+            hasher.update(b" ");
+        }
+        hasher.update(word.to_lowercase().as_bytes());
+        empty = false;
+    }
+
+    if empty {
+        String::new()
+    } else {
+        format!("{:x}", hasher.finalize())
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::{HashMap, HashSet};
+
+use strsim::{jaro, normalized_levenshtein};
+
+use crate::db::{Db, DbFile, DictWord};
+use crate::stopwords::stop_in;
+use crate::text::{normalize, stem, Token, TokenizerOptions, STEMMED};
+
+/// Tokens per insert, at most four parameters each.
+const TOKENS_BATCH: usize = 250;
+/// Words shown on each side of a hit.
+const SNIPPET_WORDS: i64 = 5;
+
+impl Db {
+    /// Id of `term` in the term dictionary, adding it when it is new. A new
+    /// term is stemmed in the `language` of the text it was found in.
+    fn term_id(&self, term: &str, language: &str, options: &TokenizerOptions) -> i64 {
+        if let Some(term_id) = self.find_term(term) {
+            return term_id;
+        }
+
+        let stem = if options.stemming {
+            stem(term, language)
+        } else {
+            String::new()
+        };
+        let mut stat = self
+            .pool
+            .prepare("INSERT INTO term(term, stem) VALUES(?, ?) RETURNING rowid;")
+            .unwrap();
+        stat.bind((1, term)).unwrap();
+        stat.bind((2, stem.as_str())).unwrap();
+        stat.next().expect("Err during inserting term");
+        let term_id = stat.read::<i64, _>("rowid").unwrap();
+        self.insert_term_grams(term_id, term);
+
+        term_id
+    }
+    /// Id of `term` in the term dictionary, `None` when no file has it.
+    pub(crate) fn find_term(&self, term: &str) -> Option<i64> {
+        let mut stat = self
+            .pool
+            .prepare("SELECT rowid FROM term WHERE term=?;")
+            .unwrap();
+        stat.bind((1, term)).unwrap();
+
+        stat.into_iter()
+            .map(|r| r.unwrap())
+            .next()
+            .map(|r| r.read::<i64, _>("rowid"))
+    }
+    /// Postings and token offsets of a file's words, token `i` being at
+    /// position `i`. Terms are looked up once per file and rows inserted in
+    /// batches. Stop words of the text's language get no postings when the
+    /// tokenizer leaves them out, their positions stay taken. `options` are
+    /// those the tokens were cut with.
+    pub fn insert_words(
+        &self,
+        file_idx: i64,
+        tokens: &[Token],
+        language: &str,
+        options: &TokenizerOptions,
+    ) {
+        let stop = if options.skip_stop_words {
+            self.stop_word_set(language)
+        } else {
+            HashSet::new()
+        };
+        let mut term_ids: HashMap<&str, i64> = HashMap::new();
+        let postings: Vec<(i64, i64)> = tokens
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| !stop.contains(&t.term))
+            .map(|(i, t)| {
+                let term_id = *term_ids
+                    .entry(&t.term)
+                    .or_insert_with(|| self.term_id(&t.term, language, options));
+                (term_id, i as i64)
+            })
+            .collect();
+
+        for chunk in postings.chunks(TOKENS_BATCH) {
+            let query = format!(
+                "INSERT INTO posting VALUES {};",
+                vec!["(?, ?, ?)"; chunk.len()].join(", ")
+            );
+            let mut stat = self.pool.prepare(query).unwrap();
+            for (i, (term_id, position)) in chunk.iter().enumerate() {
+                stat.bind((i * 3 + 1, *term_id)).unwrap();
+                stat.bind((i * 3 + 2, file_idx)).unwrap();
+                stat.bind((i * 3 + 3, *position)).unwrap();
+            }
+            stat.next().expect("Err during inserting postings");
+        }
+
+        for (batch, chunk) in tokens.chunks(TOKENS_BATCH).enumerate() {
+            let first = batch * TOKENS_BATCH;
+
+            let query = format!(
+                "INSERT INTO token VALUES {};",
+                vec!["(?, ?, ?, ?)"; chunk.len()].join(", ")
+            );
+            let mut stat = self.pool.prepare(query).unwrap();
+            for (i, token) in chunk.iter().enumerate() {
+                stat.bind((i * 4 + 1, file_idx)).unwrap();
+                stat.bind((i * 4 + 2, (first + i) as i64)).unwrap();
+                stat.bind((i * 4 + 3, token.start as i64)).unwrap();
+                stat.bind((i * 4 + 4, token.end as i64)).unwrap();
+            }
+            stat.next().expect("Err during inserting tokens");
+        }
+    }
+    /// Every (file, position) the term occurs at.
+    pub(crate) fn postings(&self, term_id: i64) -> Vec<(i64, i64)> {
+        let query = "
+	SELECT file_id, position FROM posting WHERE term_id=? ORDER BY file_id, position;
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind((1, term_id)).unwrap();
+
+        stat.into_iter()
+            .map(|r| r.unwrap())
+            .map(|r| (r.read::<i64, _>("file_id"), r.read::<i64, _>("position")))
+            .collect()
+    }
+    /// Other forms of a normalized term, the terms sharing its stem in any of
+    /// the stemmed languages or in `language` only.
+    pub(crate) fn word_forms(&self, folded: &str, language: Option<&str>) -> Vec<(i64, String)> {
+        let stems: HashSet<String> = STEMMED
+            .iter()
+            .filter(|l| language.is_none_or(|language| language == **l))
+            .map(|l| stem(folded, l))
+            .collect();
+
+        stems.iter().flat_map(|s| self.stem_terms(s)).collect()
+    }
+    /// Terms sharing a stem.
+    fn stem_terms(&self, stem: &str) -> Vec<(i64, String)> {
+        let mut stat = self
+            .pool
+            .prepare("SELECT rowid, term FROM term WHERE stem=? AND stem!='';")
+            .unwrap();
+        stat.bind((1, stem)).unwrap();
+
+        stat.into_iter()
+            .map(|r| r.unwrap())
+            .map(|r| {
+                (
+                    r.read::<i64, _>("rowid"),
+                    r.read::<&str, _>("term").to_string(),
+                )
+            })
+            .collect()
+    }
+    /// Fuzzy search of a single word in the given libraries, compared as it
+    /// is indexed, see `normalize`. `stemmed` also finds the other forms of
+    /// the word when the index keeps stems, they count as exact matches.
+    /// Stop words only match themselves in files of their language.
+    /// `language` keeps files detected to be in that language only.
+    pub fn search_word(
+        &self,
+        word: &str,
+        lensh_k: f64,
+        jer_k: f64,
+        libraries: &[i64],
+        stemmed: bool,
+        language: Option<&str>,
+    ) -> Vec<DictWord> {
+        let mut hits = Hits::new(self, libraries, language);
+        let mut res: Vec<DictWord> = vec![];
+        let folded = normalize(word, &self.word_tokenizer());
+        let stop = self.stop_word_sets(language);
+
+        let mut candidates = self.fuzzy_terms(&folded);
+        let mut forms: HashSet<i64> = HashSet::new();
+        if stemmed {
+            for (term_id, term) in self.word_forms(&folded, language) {
+                if forms.insert(term_id) && !candidates.iter().any(|(id, _)| *id == term_id) {
+                    candidates.push((term_id, term));
+                }
+            }
+        }
+
+        for (term_id, term) in candidates {
+            let jer = jaro(&folded, &term) * 100.0;
+            let lensh = normalized_levenshtein(&folded, &term) * 100.0;
+            let similarity = if forms.contains(&term_id) {
+                100.0
+            } else {
+                ((jer + lensh) / 2.0) as f32
+            };
+
+            let k_average = (lensh_k + jer_k) / 2.0;
+
+            let skipped = if term != folded {
+                stop_in(&stop, &term)
+            } else {
+                HashSet::new()
+            };
+            // with one language searched a stop word is left out of every file
+            if similarity as f64 <= k_average || (language.is_some() && !skipped.is_empty()) {
+                continue;
+            }
+            let weight = self.idf(term_id);
+
+            for (file_idx, word_idx) in self.postings(term_id) {
+                if !skipped.is_empty() && skipped.contains(hits.language(file_idx)) {
+                    continue;
+                }
+                if let Some(hit) = hits.hit(file_idx, word_idx) {
+                    res.push(DictWord {
+                        content: word.to_string(),
+                        similarity,
+                        weight,
+                        ..hit
+                    });
+                }
+            }
+        }
+
+        res
+    }
+    /// The source text around a position, as it was in the file.
+    pub(crate) fn source_snippet(&self, file_idx: i64, word_idx: i64, text: &str) -> String {
+        let (start, end) = self
+            .token_span(file_idx, word_idx - SNIPPET_WORDS, word_idx + SNIPPET_WORDS)
+            .unwrap_or_default();
+        let around = text.get(start..end).unwrap_or_default();
+
+        format!(
+            "...{}...",
+            around.split_whitespace().collect::<Vec<&str>>().join(" ")
+        )
+    }
+}
+
+/// Files and texts looked up while turning postings into results, each read
+/// once.
+pub(crate) struct Hits<'a> {
+    db: &'a Db,
+    libraries: &'a [i64],
+    language: Option<&'a str>,
+    files: HashMap<i64, DbFile>,
+    texts: HashMap<i64, Option<String>>,
+}
+
+impl<'a> Hits<'a> {
+    pub(crate) fn new(db: &'a Db, libraries: &'a [i64], language: Option<&'a str>) -> Self {
+        Self {
+            db,
+            libraries,
+            language,
+            files: HashMap::new(),
+            texts: HashMap::new(),
+        }
+    }
+    /// Language a file was detected to be in.
+    pub(crate) fn language(&mut self, file_idx: i64) -> &str {
+        let db = self.db;
+        &self
+            .files
+            .entry(file_idx)
+            .or_insert_with(|| db.get_file_idx(file_idx))
+            .language
+    }
+    /// An exact hit at a position of a file with the text around it, `None`
+    /// for files that are deleted or not searched.
+    pub(crate) fn hit(&mut self, file_idx: i64, word_idx: i64) -> Option<DictWord> {
+        let db = self.db;
+        let file = self
+            .files
+            .entry(file_idx)
+            .or_insert_with(|| db.get_file_idx(file_idx));
+        if file.deleted
+            || !self.libraries.contains(&file.library_id)
+            || self
+                .language
+                .is_some_and(|language| file.language != language)
+        {
+            return None;
+        }
+
+        Some(DictWord {
+            content: String::new(),
+            file_idx,
+            word_idx,
+            similarity: 100.0,
+            weight: 1.0,
+            file_name: file.file_name.clone(),
+            file_path: file.path.clone(),
+            file_content: match self
+                .texts
+                .entry(file_idx)
+                .or_insert_with(|| db.source_text(file_idx))
+            {
+                Some(text) => db.source_snippet(file_idx, word_idx, text),
+                None => snippet(&file.content, word_idx),
+            },
+            file_type: file.file_type.clone(),
+            file_extension: file.extension.clone(),
+            file_mime: file.mime.clone(),
+            text_hash: file.text_hash.clone(),
+            duplicates: vec![],
+        })
+    }
+}
+
+/// Up to ten words around the position, for files indexed before their text
+/// was kept.
+fn snippet(content: &str, word_idx: i64) -> String {
+    let raw_content = content.split(" ").collect::<Vec<&str>>();
+    let len_content = raw_content.len();
+    let take = if word_idx + 10 > len_content as i64 {
+        len_content
+    } else {
+        (word_idx + 10) as usize
+    };
+
+    let skip = if word_idx > 11 {
+        word_idx - 10
+    } else {
+        word_idx
+    } as usize;
+
+    format!(
+        "...{}...",
+        raw_content[skip.min(take)..take]
+            .join(" ")
+            .replace("\n", " ")
+    )
+}
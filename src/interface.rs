@@ -1,127 +1,629 @@
 use std::process::Command;
+use std::thread;
+use std::time::Duration;
 
+use chrono::{DateTime, Local};
 use iced::color;
-use iced::widget::{button, column, row, scrollable, slider, text, text_input};
-use iced::{Element, Task};
+use iced::futures::channel::oneshot;
+use iced::widget::{
+    button, checkbox, column, pick_list, row, scrollable, slider, text, text_input, Column,
+};
+use iced::{Element, Subscription, Task};
 use rfd::FileDialog;
 
-use crate::db::{self, DictWord};
+use crate::compact::human_size;
+use crate::crawler::SymlinkPolicy;
+use crate::db::{self, DictWord, DuplicateGroup, DuplicateKind};
+use crate::filetype;
+use crate::fts::TOKENIZERS;
+use crate::library::Library;
+use crate::schedule::{ScanRun, Schedule};
+use crate::stats::Stats;
+use crate::text::TokenizerOptions;
 
-#[derive(Debug, Clone)]
+/// Language filter entry that shows files in every language.
+const ANY_LANGUAGE: &str = "Any language";
+
+#[derive(Debug, Clone, Default)]
 pub enum Tab {
+    #[default]
     Scanning,
     Searching,
+    Duplicates,
 }
-impl Default for Tab {
-    fn default() -> Self {
-        Tab::Scanning
-    }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Typo tolerant word search over the term dictionary.
+    #[default]
+    Fuzzy,
+    /// Ranked SQLite FTS5 search.
+    FullText,
 }
 #[derive(Default)]
 pub struct App {
-    pub scan: String,
     pub search: String,
     pub tab: Tab,
     pub search_result: Vec<DictWord>,
     pub similarity: f32,
+    pub duplicates: Vec<DuplicateGroup>,
+    pub libraries: Vec<Library>,
+    /// Library shown in the Scanning tab.
+    pub library: i64,
+    pub new_library: String,
+    /// Libraries the Searching tab looks in.
+    pub search_libraries: Vec<i64>,
+    /// Schedules only fire for times after the app was started.
+    pub started: DateTime<Local>,
+    pub scheduled_scan: bool,
+    pub scan_status: Option<String>,
+    pub search_mode: SearchMode,
+    pub search_error: Option<String>,
+    /// Shown in the Scanning tab, refreshed after changes to the index.
+    pub stats: Stats,
+    /// Fuzzy search also matches other forms of the words.
+    pub stemmed: bool,
+    /// Fuzzy and full-text searches only show files in this language.
+    pub search_language: Option<String>,
+    /// `(language, words)` as edited in the Scanning tab.
+    pub stop_words: Vec<(String, String)>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Message {
     Scan,
+    AddRoot,
+    RemoveRoot(String),
+    RemoveFolder,
+    RemoveFile(i64),
+    Compact,
+    RefreshStats,
+    SelectLibrary(i64),
+    NewLibraryStr(String),
+    AddLibrary,
+    SearchLibrary(i64, bool),
+    SearchLanguage(String),
     Search,
     SearchStr(String),
     SwitchTab(Tab),
-    Open(DictWord),
+    Open(String),
     Slide(f32),
+    FollowSymlinks(bool),
+    SameFilesystem(bool),
+    DedupeHardlinks(bool),
+    ScheduleStr(String, String),
+    SaveSchedule(String),
+    Tick,
+    ScheduledDone(Result<Vec<ScanRun>, String>),
+    FullText(bool),
+    Stemmed(bool),
+    Tokenizer(&'static str),
+    WordTokenizer(TokenizerOptions),
+    StopWordsStr(String, String),
+    SaveStopWords(String),
 }
 
 impl App {
     pub fn update(&mut self, message: Message) -> Task<Message> {
+        // these write to the index the scheduled scan is writing to, their
+        // buttons are disabled meanwhile
+        let writes = matches!(
+            message,
+            Message::Scan
+                | Message::RemoveRoot(_)
+                | Message::RemoveFolder
+                | Message::RemoveFile(_)
+                | Message::Compact
+                | Message::Tokenizer(_)
+                | Message::WordTokenizer(_)
+                | Message::SaveStopWords(_)
+        );
+        if writes && self.scheduled_scan {
+            self.scan_status = Some("Wait for the scheduled scan to finish".to_string());
+            return Task::none();
+        }
+
         match message {
             Message::Scan => {
-                let conn = db::Db::new();
+                if let Some(library) = self.selected() {
+                    let conn = db::Db::open();
+                    conn.scan_library(library);
+                    self.stats = conn.stats();
+                }
+            }
+            Message::AddRoot => {
+                if let Some(path) = FileDialog::new().pick_folder() {
+                    db::Db::open().add_root(self.library, path.to_str().unwrap());
+                    self.reload_libraries();
+                }
+            }
+            Message::RemoveRoot(path) => {
+                let removed = db::Db::open().remove_root(self.library, &path);
+                self.scan_status = Some(format!("Removed {}: {} files", path, removed));
+                self.reload_libraries();
+                self.stats = db::Db::open().stats();
+            }
+            Message::RemoveFolder => {
+                if let Some(path) = FileDialog::new().pick_folder() {
+                    let path = path.to_str().unwrap();
+                    let conn = db::Db::open();
+                    let removed = conn.remove_dir(self.library, path);
+                    self.scan_status = Some(format!("Removed {}: {} files", path, removed));
+                    self.stats = conn.stats();
+                }
+            }
+            Message::RemoveFile(file_idx) => {
+                db::Db::open().remove_file(file_idx);
+                self.search_result.retain(|r| r.file_idx != file_idx);
+            }
+            Message::Compact => {
+                let conn = db::Db::open();
+                self.scan_status = Some(conn.compact().to_string());
+                self.stats = conn.stats();
+            }
+            Message::RefreshStats => {
+                self.stats = db::Db::open().stats();
+            }
+            Message::SelectLibrary(id) => {
+                self.library = id;
+            }
+            Message::NewLibraryStr(txt) => {
+                self.new_library = txt;
+            }
+            Message::AddLibrary => {
+                let name = self.new_library.trim();
+                let exists = self.libraries.iter().any(|l| l.name == name);
 
-                let path = FileDialog::new().pick_folder().unwrap();
-                self.scan = String::from(path.to_str().unwrap());
+                if !name.is_empty() && !exists {
+                    let id = db::Db::open().create_library(name);
+                    self.library = id;
+                    self.search_libraries.push(id);
+                    self.new_library = String::new();
+                    self.reload_libraries();
+                }
+            }
+            Message::SearchLibrary(id, checked) => {
+                self.search_libraries.retain(|l| *l != id);
+                if checked {
+                    self.search_libraries.push(id);
+                }
+            }
+            Message::Search if self.search_mode == SearchMode::FullText => {
+                let conn = db::Db::open();
 
-                conn.scan(&self.scan);
+                match conn.search_fts(
+                    &self.search,
+                    &self.search_libraries,
+                    self.search_language.as_deref(),
+                ) {
+                    Ok(results) => {
+                        self.search_result = db::collapse_duplicates(results);
+                        self.search_error = None;
+                    }
+                    Err(e) => {
+                        self.search_result = vec![];
+                        self.search_error = Some(e);
+                    }
+                }
             }
             Message::Search => {
-                let conn = db::Db::new();
-                self.search_result = vec![];
-
-                for w in self.search.split(" ") {
-                    let results =
-                        conn.search_word(w, self.similarity.into(), self.similarity.into());
-
-                    match results {
-                        Ok(results_ok) => {
-                            for res in results_ok {
-                                self.search_result.push(res);
-                            }
-                            self.search_result
-                                .sort_by(|a, b| b.similarity.partial_cmp(&a.similarity).unwrap());
-                        }
-                        Err(_) => {}
+                let conn = db::Db::open();
+
+                match conn.search(
+                    &self.search,
+                    self.similarity.into(),
+                    &self.search_libraries,
+                    self.stemmed,
+                    self.search_language.as_deref(),
+                ) {
+                    Ok(found) => {
+                        self.search_result = found.hits;
+                        self.search_error = if found.warnings.is_empty() {
+                            None
+                        } else {
+                            Some(found.warnings.join("\n"))
+                        };
+                    }
+                    Err(e) => {
+                        self.search_result = vec![];
+                        self.search_error = Some(e);
                     }
                 }
             }
+            Message::SearchLanguage(language) => {
+                self.search_language = Some(language).filter(|l| l != ANY_LANGUAGE);
+            }
             Message::SearchStr(txt) => {
                 self.search = txt.clone();
             }
             Message::SwitchTab(tb) => match tb {
                 Tab::Scanning => {
+                    self.stats = db::Db::open().stats();
                     self.tab = Tab::Scanning;
                 }
                 Tab::Searching => {
                     self.tab = Tab::Searching;
                 }
+                Tab::Duplicates => {
+                    self.duplicates = db::Db::open().duplicates();
+                    self.tab = Tab::Duplicates;
+                }
             },
-            Message::Open(res) => {
+            Message::Open(path) => {
                 Command::new("cmd")
-                    .args(["/C", "start", "", &res.file_path])
+                    .args(["/C", "start", "", &path])
                     .output()
                     .expect("Err during opening file");
             }
             Message::Slide(v) => {
                 self.similarity = v;
             }
+            Message::FollowSymlinks(v) => {
+                self.update_policy(|p| {
+                    p.symlinks = if v {
+                        SymlinkPolicy::Follow
+                    } else {
+                        SymlinkPolicy::Skip
+                    }
+                });
+            }
+            Message::SameFilesystem(v) => {
+                self.update_policy(|p| p.same_filesystem = v);
+            }
+            Message::DedupeHardlinks(v) => {
+                self.update_policy(|p| p.dedupe_hardlinks = v);
+            }
+            Message::ScheduleStr(path, txt) => {
+                if let Some(root) = self
+                    .libraries
+                    .iter_mut()
+                    .filter(|l| l.id == self.library)
+                    .flat_map(|l| l.roots.iter_mut())
+                    .find(|r| r.path == path)
+                {
+                    root.schedule = txt;
+                }
+            }
+            Message::SaveSchedule(path) => {
+                let root = self
+                    .selected()
+                    .and_then(|l| l.roots.iter().find(|r| r.path == path));
+
+                if let Some(root) = root {
+                    let schedule = root.schedule.trim();
+                    match Schedule::parse(schedule) {
+                        Err(e) if !schedule.is_empty() => {
+                            self.scan_status = Some(format!("{}: {}", path, e));
+                        }
+                        _ => {
+                            db::Db::open().set_schedule(self.library, &path, schedule);
+                            self.scan_status = None;
+                        }
+                    }
+                }
+            }
+            Message::Tick => {
+                if self.scheduled_scan {
+                    return Task::none();
+                }
+                let due = db::Db::open().due_roots(self.started);
+
+                if !due.is_empty() {
+                    self.scheduled_scan = true;
+                    return Task::perform(run_scheduled(due), Message::ScheduledDone);
+                }
+            }
+            Message::FullText(v) => {
+                self.search_mode = if v {
+                    SearchMode::FullText
+                } else {
+                    SearchMode::Fuzzy
+                };
+            }
+            Message::Stemmed(v) => {
+                self.stemmed = v;
+            }
+            Message::Tokenizer(tokenizer) => {
+                db::Db::open().set_tokenizer(tokenizer);
+            }
+            Message::WordTokenizer(options) => {
+                let conn = db::Db::open();
+                let done = conn.set_word_tokenizer(options);
+                self.scan_status = Some(format!("Re-indexed {} files", done));
+                self.stats = conn.stats();
+            }
+            Message::StopWordsStr(language, words) => {
+                if let Some(list) = self.stop_words.iter_mut().find(|(l, _)| *l == language) {
+                    list.1 = words;
+                }
+            }
+            Message::SaveStopWords(language) => {
+                if let Some((_, words)) = self.stop_words.iter().find(|(l, _)| *l == language) {
+                    let conn = db::Db::open();
+                    let done = conn.set_stop_words(&language, words);
+                    self.scan_status = Some(if done > 0 {
+                        format!("Saved {} stop words, re-indexed {} files", language, done)
+                    } else {
+                        format!("Saved {} stop words", language)
+                    });
+                    self.stats = conn.stats();
+                }
+            }
+            Message::ScheduledDone(runs) => {
+                self.scheduled_scan = false;
+                self.scan_status = Some(match runs {
+                    Ok(runs) => {
+                        let roots: Vec<String> = runs
+                            .iter()
+                            .map(|r| match r.status.as_str() {
+                                "ok" => r.root.clone(),
+                                status => format!("{} ({})", r.root, status),
+                            })
+                            .collect();
+                        format!("Scheduled scan finished: {}", roots.join(", "))
+                    }
+                    Err(e) => format!("Scheduled scan failed: {}", e),
+                });
+                self.stats = db::Db::open().stats();
+            }
         }
         Task::none()
     }
 
-    pub fn view(&self) -> Element<Message> {
+    pub fn subscription(&self) -> Subscription<Message> {
+        iced::time::every(Duration::from_secs(30)).map(|_| Message::Tick)
+    }
+
+    fn selected(&self) -> Option<&Library> {
+        self.libraries.iter().find(|l| l.id == self.library)
+    }
+    fn reload_libraries(&mut self) {
+        self.libraries = db::Db::open().libraries();
+    }
+    fn update_policy(&mut self, f: impl FnOnce(&mut crate::crawler::CrawlPolicy)) {
+        if let Some(library) = self.libraries.iter_mut().find(|l| l.id == self.library) {
+            f(&mut library.policy);
+            db::Db::open().save_policy(library.id, &library.policy);
+        }
+    }
+
+    /// Stop-word lists, one line of words per language.
+    fn stop_words_view(&self) -> Column<'_, Message> {
+        let mut lists = column![text("Stop words, left out of word searches:")].spacing(6);
+        for (language, words) in self.stop_words.iter() {
+            let edited = language.clone();
+            lists = lists.push(
+                row![
+                    text(language.as_str()).width(60),
+                    text_input("Words separated by spaces", words)
+                        .on_input(move |s| Message::StopWordsStr(edited.clone(), s)),
+                    button("Save").on_press_maybe(
+                        (!self.scheduled_scan).then(|| Message::SaveStopWords(language.clone()))
+                    )
+                ]
+                .spacing(12),
+            );
+        }
+
+        lists
+    }
+    /// Index statistics for the Scanning tab.
+    fn stats_view(&self) -> Column<'_, Message> {
+        let stats = &self.stats;
+        let by_type: Vec<String> = stats
+            .by_type
+            .iter()
+            .map(|(kind, n)| format!("{} {}", kind, n))
+            .collect();
+        let by_language: Vec<String> = stats
+            .by_language
+            .iter()
+            .map(|(language, n)| format!("{} {}", language, n))
+            .collect();
+        let top_terms: Vec<String> = stats
+            .top_terms
+            .iter()
+            .map(|(term, n)| format!("{} ({})", term, n))
+            .collect();
+        let last_scan = match &stats.last_scan {
+            Some(run) => format!(
+                "Last scan: {} {} ({})",
+                run.started().format("%Y-%m-%d %H:%M"),
+                run.root,
+                run.status
+            ),
+            None => "Last scan: never".to_string(),
+        };
+
+        let mut failed =
+            column![text(format!("Failed extractions: {}", stats.failed.len()))].spacing(2);
+        for path in stats.failed.iter().take(5) {
+            failed = failed.push(text(path.as_str()).size(12).color(color!(0x999999)));
+        }
+        if stats.failed.len() > 5 {
+            failed = failed.push(
+                text(format!("and {} more", stats.failed.len() - 5))
+                    .size(12)
+                    .color(color!(0x999999)),
+            );
+        }
+
+        column![
+            row![
+                text(format!("Files: {}", stats.files)),
+                text(format!("Folders: {}", stats.dirs)),
+                text(format!("Index size: {}", human_size(stats.size_on_disk)))
+            ]
+            .spacing(12),
+            text(format!("By type: {}", by_type.join(", "))),
+            text(format!("By language: {}", by_language.join(", "))),
+            text(format!(
+                "Words: {}, unique: {}",
+                stats.terms, stats.unique_terms
+            )),
+            text(format!("Top words: {}", top_terms.join(", "))),
+            text(last_scan),
+            failed,
+            row![
+                button("Refresh").on_press(Message::RefreshStats),
+                button("Compact index")
+                    .on_press_maybe((!self.scheduled_scan).then_some(Message::Compact))
+            ]
+            .spacing(12)
+        ]
+        .spacing(4)
+    }
+
+    pub fn view(&self) -> Element<'_, Message> {
         let tab = match &self.tab {
             Tab::Scanning => {
-                let data = db::Db::new();
+                let data = db::Db::open();
+                let words = data.word_tokenizer();
+                let idle = !self.scheduled_scan;
+
+                let mut libraries = row![].spacing(6);
+                for library in self.libraries.iter() {
+                    let label = if library.id == self.library {
+                        format!("[{}]", library.name)
+                    } else {
+                        library.name.clone()
+                    };
+                    libraries = libraries
+                        .push(button(text(label)).on_press(Message::SelectLibrary(library.id)));
+                }
 
-                let scanning_path = format!("Scanning path path is: {}", self.scan);
+                let mut settings = column![].spacing(6);
+                if let Some(library) = self.selected() {
+                    let mut roots = column![].spacing(6);
+                    for root in library.roots.iter() {
+                        let path = root.path.clone();
+                        let last_run = match data.last_run(library.id, &root.path) {
+                            Some(run) => format!(
+                                "Last run {} ({}): {}, {:.1}s, {} indexed, {} unchanged, {} removed",
+                                run.started().format("%Y-%m-%d %H:%M"),
+                                run.trigger,
+                                run.status,
+                                run.duration_ms as f64 / 1000.0,
+                                run.indexed,
+                                run.skipped,
+                                run.removed
+                            ),
+                            None => "Never scanned".to_string(),
+                        };
+
+                        roots = roots.push(
+                            column![
+                                row![
+                                    text(root.path.as_str()),
+                                    text_input("Schedule, e.g. 0 2 * * *", &root.schedule)
+                                        .on_input(move |s| Message::ScheduleStr(path.clone(), s))
+                                        .width(200),
+                                    button("Save")
+                                        .on_press(Message::SaveSchedule(root.path.clone())),
+                                    button("Remove").on_press_maybe(
+                                        idle.then(|| Message::RemoveRoot(root.path.clone()))
+                                    )
+                                ]
+                                .spacing(12),
+                                text(last_run).size(12).color(color!(0x999999))
+                            ]
+                            .spacing(2),
+                        );
+                    }
+                    if let Some(status) = &self.scan_status {
+                        roots = roots.push(text(status.as_str()));
+                    }
+                    if self.scheduled_scan {
+                        roots = roots.push(text("Scheduled scan is running..."));
+                    }
+
+                    settings = settings
+                        .push(text(format!("Roots of {}:", library.name)))
+                        .push(roots)
+                        .push(
+                            row![
+                                button("Add root").on_press(Message::AddRoot),
+                                button("Scan").on_press_maybe(idle.then_some(Message::Scan)),
+                                button("Remove folder")
+                                    .on_press_maybe(idle.then_some(Message::RemoveFolder))
+                            ]
+                            .spacing(12),
+                        )
+                        .push(
+                            row![
+                                checkbox(
+                                    "Follow symlinks",
+                                    library.policy.symlinks == SymlinkPolicy::Follow
+                                )
+                                .on_toggle(Message::FollowSymlinks),
+                                checkbox("Stay on one filesystem", library.policy.same_filesystem)
+                                    .on_toggle(Message::SameFilesystem),
+                                checkbox("Skip hardlinked copies", library.policy.dedupe_hardlinks)
+                                    .on_toggle(Message::DedupeHardlinks),
+                            ]
+                            .spacing(12),
+                        );
+                }
 
                 column![
-                    text("Scan the path to library"),
-                    row![button("Scan").on_press(Message::Scan), text(scanning_path)].spacing(12),
+                    text("Libraries"),
+                    libraries,
                     row![
-                        text(format!("Files: {}", data.files)),
-                        text(format!("Folders: {}", data.dirs))
+                        text_input("New library name", &self.new_library)
+                            .on_input(Message::NewLibraryStr),
+                        button("Add library").on_press(Message::AddLibrary)
                     ]
+                    .spacing(12),
+                    settings,
+                    row![
+                        text("Full-text tokenizer:"),
+                        pick_list(
+                            &TOKENIZERS[..],
+                            TOKENIZERS.iter().find(|t| **t == data.tokenizer()).copied(),
+                            Message::Tokenizer
+                        )
+                    ]
+                    .spacing(12),
+                    words_view(words, idle),
+                    self.stop_words_view(),
+                    self.stats_view(),
                 ]
+                .spacing(6)
             }
             Tab::Searching => {
                 let mut results = column![].spacing(16);
 
                 for res in self.search_result.iter() {
-                    let head = format!("{} - {:.1}%", res.file_name.as_str(), res.similarity);
+                    let mut head = match self.search_mode {
+                        SearchMode::Fuzzy => format!(
+                            "{} - {:.1}% ({})",
+                            res.file_name.as_str(),
+                            res.similarity,
+                            res.content
+                        ),
+                        SearchMode::FullText => {
+                            format!("{} - score {:.2}", res.file_name.as_str(), res.similarity)
+                        }
+                    };
+                    if !res.duplicates.is_empty() {
+                        head.push_str(&format!(" (+{} copies)", res.duplicates.len()));
+                    }
+                    // flag files whose name lies about their format
+                    let file_info = if filetype::misnamed(&res.file_extension, &res.file_type) {
+                        format!(
+                            "{}  {} (named .{})",
+                            res.file_path, res.file_mime, res.file_extension
+                        )
+                    } else {
+                        format!("{}  {}", res.file_path, res.file_mime)
+                    };
 
                     let content = column![
                         row![
-                            button("b").on_press(Message::Open(res.clone())),
-                            column![
-                                text(head.clone()),
-                                text(res.file_path.as_str())
-                                    .size(12)
-                                    .color(color!(0x999999))
-                            ]
+                            button("b").on_press(Message::Open(res.file_path.clone())),
+                            button("x").on_press_maybe(
+                                (!self.scheduled_scan).then_some(Message::RemoveFile(res.file_idx))
+                            ),
+                            column![text(head), text(file_info).size(12).color(color!(0x999999))]
                         ]
                         .spacing(4),
                         text(res.file_content.clone())
@@ -130,28 +632,96 @@ impl App {
                     results = results.push(content);
                 }
 
+                let mut search_in = row![text("Search in:")].spacing(12);
+                for library in self.libraries.iter() {
+                    let id = library.id;
+                    search_in = search_in.push(
+                        checkbox(library.name.as_str(), self.search_libraries.contains(&id))
+                            .on_toggle(move |checked| Message::SearchLibrary(id, checked)),
+                    );
+                }
+                let languages: Vec<String> = std::iter::once(ANY_LANGUAGE.to_string())
+                    .chain(self.stats.by_language.iter().map(|(l, _)| l.clone()))
+                    .collect();
+                search_in = search_in.push(pick_list(
+                    languages,
+                    Some(
+                        self.search_language
+                            .clone()
+                            .unwrap_or_else(|| ANY_LANGUAGE.to_string()),
+                    ),
+                    Message::SearchLanguage,
+                ));
+
                 let current_similarity = format!("Accurate: {:.0}%", self.similarity);
+                let mut options = row![checkbox(
+                    "Full-text (exact, prefix*, \"phrase\", OR)",
+                    self.search_mode == SearchMode::FullText
+                )
+                .on_toggle(Message::FullText)]
+                .spacing(12);
+                if self.search_mode == SearchMode::Fuzzy {
+                    options = options
+                        .push(
+                            checkbox("Match word forms", self.stemmed).on_toggle(Message::Stemmed),
+                        )
+                        .push(text(current_similarity))
+                        .push(slider(55.0..=100.0, self.similarity, Message::Slide));
+                }
+                let search_error = match &self.search_error {
+                    Some(e) => text(e.as_str()).color(color!(0xcc3333)),
+                    None => text(""),
+                };
+
                 column![
                     row![row![row![
-                        text_input("Search by word or phrase", &self.search)
-                            .on_input(Message::SearchStr),
+                        text_input(
+                            "lease AND (rent OR \"fixed term\") NOT draft type:pdf path:legal/",
+                            &self.search
+                        )
+                        .on_input(Message::SearchStr),
                         button("Search").on_press(Message::Search)
                     ]
                     .spacing(12),]
                     .spacing(2)]
                     .spacing(12),
-                    row![
-                        text(current_similarity),
-                        slider(55.0..=100.0, self.similarity, Message::Slide),
-                    ].spacing(6),
+                    search_error,
+                    search_in,
+                    options,
                     scrollable(results)
-                ].spacing(6)
+                ]
+                .spacing(6)
+            }
+            Tab::Duplicates => {
+                let mut groups = column![].spacing(16);
+
+                for group in self.duplicates.iter() {
+                    let kind = match group.kind {
+                        DuplicateKind::Identical => "identical files",
+                        DuplicateKind::SameText => "same text",
+                    };
+                    let mut copies =
+                        column![text(format!("{} copies, {}", group.files.len(), kind))].spacing(2);
+
+                    for file in group.files.iter() {
+                        copies =
+                            copies.push(text(file.path.as_str()).size(12).color(color!(0x999999)));
+                    }
+                    groups = groups.push(copies);
+                }
+
+                column![
+                    text(format!("Duplicate groups: {}", self.duplicates.len())),
+                    scrollable(groups)
+                ]
+                .spacing(6)
             }
         };
         let content = column![
             row![
                 button("Scanning").on_press(Message::SwitchTab(Tab::Scanning)),
-                button("Searching").on_press(Message::SwitchTab(Tab::Searching))
+                button("Searching").on_press(Message::SwitchTab(Tab::Searching)),
+                button("Duplicates").on_press(Message::SwitchTab(Tab::Duplicates))
             ],
             row![tab].spacing(12),
         ]
@@ -160,3 +730,60 @@ impl App {
         content.padding(24).into()
     }
 }
+
+/// Tokenizer options, any change re-indexes the kept texts. They can't be
+/// changed unless `enabled`.
+fn words_view(words: TokenizerOptions, enabled: bool) -> Column<'static, Message> {
+    let option = |label: &'static str, on: bool, set: fn(&mut TokenizerOptions, bool)| {
+        checkbox(label, on).on_toggle_maybe(enabled.then_some(move |v| {
+            let mut options = words;
+            set(&mut options, v);
+            Message::WordTokenizer(options)
+        }))
+    };
+
+    column![
+        row![
+            text("Words:"),
+            option("Keep hyphenated words", words.hyphens, |o, v| o.hyphens = v),
+            option("Keep apostrophes", words.apostrophes, |o, v| o
+                .apostrophes =
+                v),
+            option("Index numbers", words.numbers, |o, v| o.numbers = v),
+        ]
+        .spacing(12),
+        row![
+            option("CJK character pairs", words.cjk_bigrams, |o, v| o
+                .cjk_bigrams =
+                v),
+            option("Ignore accents", words.diacritics, |o, v| o.diacritics = v),
+            option(
+                "Stem English, Russian and German",
+                words.stemming,
+                |o, v| o.stemming = v
+            ),
+            option("Leave stop words out", words.skip_stop_words, |o, v| o
+                .skip_stop_words =
+                v),
+        ]
+        .spacing(12)
+    ]
+    .spacing(6)
+}
+
+/// Runs due scans on their own thread so the window stays responsive.
+async fn run_scheduled(due: Vec<(Library, String)>) -> Result<Vec<ScanRun>, String> {
+    let (tx, rx) = oneshot::channel();
+
+    thread::spawn(move || {
+        let conn = db::Db::open();
+        let runs: Vec<ScanRun> = due
+            .iter()
+            .map(|(library, root)| conn.scan_root(library, root, "scheduled"))
+            .collect();
+        conn.count_terms();
+        tx.send(runs).ok();
+    });
+
+    rx.await.map_err(|_| "the scan thread stopped".to_string())
+}
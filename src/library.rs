@@ -0,0 +1,127 @@
+use crate::crawler::{CrawlPolicy, SymlinkPolicy};
+use crate::db::Db;
+
+#[derive(Debug, Clone)]
+pub struct Root {
+    pub path: String,
+    /// Cron expression for background re-scans, empty when not scheduled.
+    pub schedule: String,
+}
+
+/// A named set of root folders scanned with the same settings.
+#[derive(Debug, Clone)]
+pub struct Library {
+    pub id: i64,
+    pub name: String,
+    pub policy: CrawlPolicy,
+    pub roots: Vec<Root>,
+}
+
+impl Db {
+    pub fn libraries(&self) -> Vec<Library> {
+        let query = "
+	SELECT rowid, * FROM library ORDER BY name;
+	";
+        let mut libraries: Vec<Library> = vec![];
+
+        for r in self
+            .pool
+            .prepare(query)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap())
+        {
+            let symlinks = if r.read::<i64, _>("follow_symlinks") == 1 {
+                SymlinkPolicy::Follow
+            } else {
+                SymlinkPolicy::Skip
+            };
+
+            libraries.push(Library {
+                id: r.read::<i64, _>("rowid"),
+                name: r.read::<&str, _>("name").to_string(),
+                policy: CrawlPolicy {
+                    symlinks,
+                    same_filesystem: r.read::<i64, _>("same_filesystem") == 1,
+                    dedupe_hardlinks: r.read::<i64, _>("dedupe_hardlinks") == 1,
+                },
+                roots: vec![],
+            });
+        }
+
+        for library in libraries.iter_mut() {
+            library.roots = self.roots(library.id);
+        }
+
+        libraries
+    }
+    fn roots(&self, library_id: i64) -> Vec<Root> {
+        let stat = self
+            .pool
+            .prepare("SELECT path, schedule FROM library_root WHERE library_id=? ORDER BY path;")
+            .unwrap()
+            .into_iter()
+            .bind((1, library_id))
+            .unwrap();
+
+        stat.map(|r| r.unwrap())
+            .map(|r| Root {
+                path: r.read::<&str, _>("path").to_string(),
+                schedule: r.read::<&str, _>("schedule").to_string(),
+            })
+            .collect()
+    }
+    pub fn create_library(&self, name: &str) -> i64 {
+        let policy = CrawlPolicy::default();
+        let query = "
+	INSERT INTO library VALUES(?, ?, ?, ?) RETURNING rowid;
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind((1, name)).unwrap();
+        stat.bind((2, (policy.symlinks == SymlinkPolicy::Follow) as i64))
+            .unwrap();
+        stat.bind((3, policy.same_filesystem as i64)).unwrap();
+        stat.bind((4, policy.dedupe_hardlinks as i64)).unwrap();
+        stat.next().expect("Err during creating library");
+
+        stat.read::<i64, _>("rowid").unwrap()
+    }
+    pub fn save_policy(&self, library_id: i64, policy: &CrawlPolicy) {
+        let query = "
+	UPDATE library SET follow_symlinks=?, same_filesystem=?, dedupe_hardlinks=? WHERE rowid=?;
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind((1, (policy.symlinks == SymlinkPolicy::Follow) as i64))
+            .unwrap();
+        stat.bind((2, policy.same_filesystem as i64)).unwrap();
+        stat.bind((3, policy.dedupe_hardlinks as i64)).unwrap();
+        stat.bind((4, library_id)).unwrap();
+        stat.next().expect("Err during saving library");
+    }
+    pub fn add_root(&self, library_id: i64, path: &str) {
+        let query = "
+	INSERT INTO library_root VALUES(?, ?, '');
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind((1, library_id)).unwrap();
+        stat.bind((2, path)).unwrap();
+        stat.next().expect("Err during adding root");
+    }
+    pub fn set_schedule(&self, library_id: i64, path: &str, schedule: &str) {
+        let query = "
+	UPDATE library_root SET schedule=? WHERE library_id=? AND path=?;
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind((1, schedule)).unwrap();
+        stat.bind((2, library_id)).unwrap();
+        stat.bind((3, path)).unwrap();
+        stat.next().expect("Err during saving schedule");
+    }
+    /// Scans every root of the library with its own settings.
+    pub fn scan_library(&self, library: &Library) {
+        for root in library.roots.iter() {
+            self.scan_root(library, &root.path, "manual");
+        }
+        self.count_terms();
+    }
+}
@@ -1,24 +1,181 @@
+mod bundle;
+mod compact;
+mod config;
 mod crawler;
 mod db;
 mod docx;
+mod filetype;
+mod fts;
+mod fuzzy;
+mod hash;
+mod index;
 mod interface;
+mod library;
 mod pdf;
+mod query;
+mod remove;
+mod schedule;
+mod schema;
+mod stats;
+mod stopwords;
+mod text;
+mod verify;
 
 use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
 
+use chrono::Local;
+use db::Db;
 use iced::{self, Task};
-use interface::{App, Tab};
+use interface::{App, SearchMode, Tab};
+
+const USAGE: &str = "Usage: book_worm [--data-dir <path>] [verify [--repair] | compact | export <bundle> [--library <name>] | import <bundle> [--root <old>=<new>]...]";
 
 fn main() -> iced::Result {
-    let scan_path_init = env::current_dir().unwrap();
-    
+    let mut data_dir = None;
+    let mut command: Option<String> = None;
+    let mut repair = false;
+    let mut bundle: Option<PathBuf> = None;
+    let mut rebase: Vec<(String, String)> = vec![];
+    let mut library: Option<String> = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--data-dir" => match args.next() {
+                Some(path) => data_dir = Some(PathBuf::from(path)),
+                None => exit(USAGE),
+            },
+            "verify" | "compact" | "export" | "import" if command.is_none() => command = Some(arg),
+            "--repair" if command.as_deref() == Some("verify") => repair = true,
+            "--library" if command.as_deref() == Some("export") => match args.next() {
+                Some(name) => library = Some(name),
+                None => exit(USAGE),
+            },
+            "--root" if command.as_deref() == Some("import") => {
+                match args.next().as_deref().and_then(|m| m.split_once('=')) {
+                    Some((old, new)) => rebase.push((old.to_string(), new.to_string())),
+                    None => exit(USAGE),
+                }
+            }
+            a if matches!(command.as_deref(), Some("export" | "import")) && bundle.is_none() => {
+                bundle = Some(PathBuf::from(a))
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            a => exit(&format!("Unknown argument `{}`\n{}", a, USAGE)),
+        }
+    }
+
+    let config = config::init(data_dir);
+    // held until the window is closed
+    let _lock = config.lock().unwrap_or_else(|e| exit(&e));
+    match command.as_deref() {
+        Some("verify") => {
+            check_index(repair);
+            return Ok(());
+        }
+        Some("compact") => {
+            println!("{}", Db::new().compact());
+            return Ok(());
+        }
+        Some("export") => {
+            let bundle = bundle.unwrap_or_else(|| exit(USAGE));
+            let manifest = Db::new()
+                .export(&bundle, library.as_deref())
+                .unwrap_or_else(|e| exit(&e));
+            println!(
+                "Exported {} roots to {}",
+                manifest.roots.len(),
+                bundle.display()
+            );
+            return Ok(());
+        }
+        Some("import") => {
+            let bundle = bundle.unwrap_or_else(|| exit(USAGE));
+            let manifest = bundle::import(&bundle, &rebase).unwrap_or_else(|e| exit(&e));
+            for (library, root) in manifest.roots.iter() {
+                let root = rebase
+                    .iter()
+                    .find(|(old, _)| old == root)
+                    .map_or(root, |(_, new)| new);
+                let missing = if Path::new(root).exists() {
+                    ""
+                } else {
+                    " (not found, use --root to move it)"
+                };
+                println!("{}: {}{}", library, root, missing);
+            }
+            return Ok(());
+        }
+        _ => (),
+    }
+    let db = Db::new();
+    let libraries = db.libraries();
+
     let init = App {
-        scan: String::from(scan_path_init.to_string_lossy()),
         search: String::new(),
         tab: Tab::Scanning,
         search_result: vec![],
-        similarity: 55.0
+        similarity: 55.0,
+        duplicates: vec![],
+        library: libraries[0].id,
+        search_libraries: libraries.iter().map(|l| l.id).collect(),
+        libraries,
+        new_library: String::new(),
+        started: Local::now(),
+        scheduled_scan: false,
+        scan_status: None,
+        search_mode: SearchMode::Fuzzy,
+        search_error: None,
+        stats: db.stats(),
+        stemmed: false,
+        search_language: None,
+        stop_words: stopwords::LANGUAGES
+            .iter()
+            .map(|(language, _)| (language.to_string(), db.stop_words(language).join(" ")))
+            .collect(),
     };
 
-    iced::application("Book Worm", App::update, App::view).run_with(|| (init, Task::none()))
+    iced::application("Book Worm", App::update, App::view)
+        .subscription(App::subscription)
+        .run_with(|| (init, Task::none()))
+}
+
+/// `verify` command: lists what is wrong with the index and repairs it when
+/// asked to. Exits with 1 while problems are left.
+fn check_index(repair: bool) {
+    let db = Db::new();
+    let issues = db.verify();
+    for issue in issues.iter() {
+        println!("{}", issue);
+    }
+    if issues.is_empty() {
+        println!("The index is fine");
+        return;
+    }
+    if !repair {
+        println!(
+            "{} problems, run `book_worm verify --repair` to fix them",
+            issues.len()
+        );
+        process::exit(1);
+    }
+
+    db.repair(&issues);
+    let left = db.verify();
+    println!("Repaired, {} problems left", left.len());
+    for issue in left.iter() {
+        println!("{}", issue);
+    }
+    if !left.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn exit(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(2)
 }
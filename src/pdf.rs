@@ -1,15 +1,36 @@
-use lopdf::Document;
+use lopdf::{decode_text_string, Document};
 
-pub fn get(file_path: &str) -> Result<String, ()> {
-    let doc = Document::load(file_path).unwrap();
+/// Text of every page and the author.
+pub fn get(file_path: &str) -> Result<(String, String), ()> {
+    let doc = Document::load(file_path).map_err(|_| ())?;
     let pages = doc.get_pages();
     let mut res = String::new();
     for page in pages {
         let p = [page.0];
-        let text = doc.extract_text(&p).unwrap();
+        let text = doc.extract_text(&p).map_err(|_| ())?;
 
         res.push_str(text.as_str());
     }
 
-    Ok(res)
+    Ok((res, info_author(&doc)))
+}
+
+/// Author from the document information, empty when there is none.
+pub fn author(file_path: &str) -> String {
+    match Document::load(file_path) {
+        Ok(doc) => info_author(&doc),
+        Err(_) => String::new(),
+    }
+}
+
+fn info_author(doc: &Document) -> String {
+    doc.trailer
+        .get(b"Info")
+        .and_then(|info| doc.dereference(info))
+        .and_then(|(_, info)| info.as_dict())
+        .and_then(|info| info.get(b"Author"))
+        .and_then(|author| doc.dereference(author))
+        .and_then(|(_, author)| decode_text_string(author))
+        .map(|author| author.trim().to_string())
+        .unwrap_or_default()
 }
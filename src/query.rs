@@ -0,0 +1,996 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use regex::Regex;
+
+use crate::db::{collapse_duplicates, Db, DictWord};
+use crate::index::Hits;
+use crate::stopwords::stop_in;
+use crate::text::{normalize, tokens};
+
+/// Words allowed between the sides of a `NEAR` without `/n`, as in FTS5.
+const NEAR_DISTANCE: i64 = 10;
+/// Most terms a wildcard or regex is expanded to, the others are left out of
+/// the search with a warning.
+const EXPANSION_LIMIT: usize = 500;
+
+/// A parsed fuzzy search query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    /// A word, matched fuzzily on its own and exactly next to `NEAR`.
+    Word(String),
+    /// Words in this order right after each other, written in quotes.
+    Phrase(String),
+    /// Both sides at most `distance` words apart, in either order, written
+    /// `a NEAR/3 b`.
+    Near(Box<Query>, Box<Query>, i64),
+    /// Every term of the dictionary the pattern matches.
+    Terms(Pattern),
+    /// Files whose field has the value, written `type:pdf`.
+    Field(Field, String),
+    /// Files the query doesn't match.
+    Not(Box<Query>),
+    /// Files every part matches, written `a AND b` or `a b`.
+    And(Vec<Query>),
+    /// Files any part matches.
+    Or(Vec<Query>),
+}
+
+/// A pattern of terms, matched against the whole term dictionary.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `contr*` or `c?t`, `*` standing for any letters and `?` for one.
+    Wildcard(String),
+    /// `/colou?r/`, a regular expression a whole term has to match.
+    Regex(String),
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Wildcard(pattern) => write!(f, "{}", pattern),
+            Pattern::Regex(regex) => write!(f, "/{}/", regex.replace('/', "\\/")),
+        }
+    }
+}
+
+/// What a field qualifier looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// The detected file type, `type:pdf`.
+    Type,
+    /// A part of the path, `path:legal/`.
+    Path,
+    /// A part of the author named in the file's properties, `author:Ivanov`.
+    Author,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "type" => Some(Field::Type),
+            "path" => Some(Field::Path),
+            "author" => Some(Field::Author),
+            _ => None,
+        }
+    }
+    fn name(self) -> &'static str {
+        match self {
+            Field::Type => "type",
+            Field::Path => "path",
+            Field::Author => "author",
+        }
+    }
+    /// Column of `file` holding the field.
+    fn column(self) -> &'static str {
+        match self {
+            Field::Type => "file_type",
+            Field::Path => "path",
+            Field::Author => "author",
+        }
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // parts joined by an operator binding weaker than the one around them
+        let group = |part: &Query| match part {
+            Query::And(_) | Query::Or(_) => format!("({})", part),
+            _ => part.to_string(),
+        };
+        let join = |parts: &[Query], operator: &str| {
+            parts
+                .iter()
+                .map(group)
+                .collect::<Vec<String>>()
+                .join(operator)
+        };
+
+        match self {
+            Query::Word(word) => write!(f, "{}", word),
+            Query::Phrase(phrase) => write!(f, "\"{}\"", phrase),
+            Query::Near(left, right, distance) => {
+                write!(f, "{} NEAR/{} {}", left, distance, right)
+            }
+            Query::Terms(pattern) => write!(f, "{}", pattern),
+            Query::Field(field, value) if value.contains(char::is_whitespace) => {
+                write!(f, "{}:\"{}\"", field.name(), value)
+            }
+            Query::Field(field, value) => write!(f, "{}:{}", field.name(), value),
+            Query::Not(inner) => write!(f, "NOT {}", group(inner)),
+            Query::And(parts) => write!(f, "{}", join(parts, " AND ")),
+            Query::Or(parts) => write!(f, "{}", join(parts, " OR ")),
+        }
+    }
+}
+
+/// A piece of a query as written.
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Near(i64),
+    Word(String),
+    Phrase(String),
+    Pattern(Pattern),
+    Field(Field, String),
+}
+
+impl fmt::Display for Lexeme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lexeme::Open => write!(f, "("),
+            Lexeme::Close => write!(f, ")"),
+            Lexeme::And => write!(f, "AND"),
+            Lexeme::Or => write!(f, "OR"),
+            Lexeme::Not => write!(f, "NOT"),
+            Lexeme::Near(distance) => write!(f, "NEAR/{}", distance),
+            Lexeme::Word(word) => write!(f, "{}", word),
+            Lexeme::Phrase(phrase) => write!(f, "\"{}\"", phrase),
+            Lexeme::Pattern(pattern) => write!(f, "{}", pattern),
+            Lexeme::Field(field, value) => write!(f, "{}:{}", field.name(), value),
+        }
+    }
+}
+
+/// Where a part of a query matches, `(first, last)` positions by file.
+type Spans = HashMap<i64, Vec<(i64, i64)>>;
+/// Files a query matches with the hits found in them. Files matched by a
+/// field or `NOT` alone have none.
+type Matches = HashMap<i64, Vec<DictWord>>;
+/// Positions of a word by file.
+type Positions = HashMap<i64, HashSet<i64>>;
+
+/// Reads a query, `None` when it is empty.
+///
+/// `OR` binds weaker than `AND`, which can be left out between parts, `NOT`
+/// binds to the part after it and `NEAR` only joins words, phrases and
+/// patterns, so `a b OR NOT c` is `(a AND b) OR (NOT c)`. Operators are
+/// written in capitals, `and` is a word. A word with `*` or `?` in it is a
+/// wildcard and `/.../` a regex. Errors tell what is wrong and at which
+/// character.
+pub fn parse(query: &str) -> Result<Option<Query>, String> {
+    let mut parser = Parser {
+        lexemes: lex(query)?,
+        next: 0,
+    };
+    if parser.lexemes.is_empty() {
+        return Ok(None);
+    }
+
+    let parsed = parser.or()?;
+    match parser.lexemes.get(parser.next) {
+        Some((at, lexeme)) => Err(format!("Unexpected {} at {}", lexeme, at)),
+        None => Ok(Some(parsed)),
+    }
+}
+
+/// Cuts a query into lexemes, each with the character it starts at,
+/// counting from 1.
+fn lex(query: &str) -> Result<Vec<(usize, Lexeme)>, String> {
+    let mut res: Vec<(usize, Lexeme)> = vec![];
+    let mut rest = query;
+
+    loop {
+        rest = rest.trim_start();
+        let at = query[..query.len() - rest.len()].chars().count() + 1;
+        let lexeme = if rest.is_empty() {
+            break;
+        } else if let Some(after) = rest.strip_prefix('(') {
+            rest = after;
+            Lexeme::Open
+        } else if let Some(after) = rest.strip_prefix(')') {
+            rest = after;
+            Lexeme::Close
+        } else if rest.starts_with('"') {
+            let (phrase, after) = quoted(rest, at)?;
+            rest = after;
+            Lexeme::Phrase(phrase)
+        } else if rest.starts_with('/') {
+            let (regex, after) = regex(rest, at)?;
+            rest = after;
+            Lexeme::Pattern(Pattern::Regex(regex))
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || matches!(c, '"' | '(' | ')'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            rest = &rest[end..];
+
+            match word {
+                "AND" => Lexeme::And,
+                "OR" => Lexeme::Or,
+                "NOT" => Lexeme::Not,
+                _ => match (near_operator(word)?, word.split_once(':')) {
+                    (Some(distance), _) => Lexeme::Near(distance),
+                    (None, Some((name, value))) if Field::parse(name).is_some() => {
+                        let field = Field::parse(name).unwrap();
+                        let value = if value.is_empty() && rest.starts_with('"') {
+                            let (value, after) = quoted(rest, at + word.chars().count())?;
+                            rest = after;
+                            value
+                        } else {
+                            value.to_string()
+                        };
+                        if value.trim().is_empty() {
+                            return Err(format!("{}: at {} needs a value", field.name(), at));
+                        }
+                        Lexeme::Field(field, value.trim().to_string())
+                    }
+                    _ if word.contains(['*', '?']) => {
+                        Lexeme::Pattern(Pattern::Wildcard(word.to_string()))
+                    }
+                    _ => Lexeme::Word(word.to_string()),
+                },
+            }
+        };
+        res.push((at, lexeme));
+    }
+
+    Ok(res)
+}
+
+/// The text of a quoted phrase at the start of `rest` and what follows it.
+fn quoted(rest: &str, at: usize) -> Result<(String, &str), String> {
+    let quoted = &rest[1..];
+    let end = quoted
+        .find('"')
+        .ok_or_else(|| format!("Unclosed quote at {}", at))?;
+    let phrase = &quoted[..end];
+    if phrase.trim().is_empty() {
+        return Err(format!("Empty phrase at {}", at));
+    }
+
+    Ok((phrase.to_string(), &quoted[end + 1..]))
+}
+
+/// The regex between slashes at the start of `rest` and what follows it.
+/// `\/` is a slash inside the regex.
+fn regex(rest: &str, at: usize) -> Result<(String, &str), String> {
+    let body = &rest[1..];
+    let mut escaped = false;
+    let end = body
+        .char_indices()
+        .find(|(_, c)| {
+            let closes = *c == '/' && !escaped;
+            escaped = *c == '\\' && !escaped;
+            closes
+        })
+        .map(|(i, _)| i)
+        .ok_or_else(|| format!("Unclosed regex at {}", at))?;
+    let regex = body[..end].replace("\\/", "/");
+    if regex.is_empty() {
+        return Err(format!("Empty regex at {}", at));
+    }
+    if let Err(e) = Regex::new(&regex) {
+        let reason = e.to_string();
+        let reason = reason.lines().last().unwrap_or_default();
+        return Err(format!(
+            "Bad regex at {}: {}",
+            at,
+            reason.trim_start_matches("error: ")
+        ));
+    }
+
+    Ok((regex, &body[end + 1..]))
+}
+
+/// Distance of a `NEAR` or `NEAR/n` operator, `None` for other words.
+fn near_operator(word: &str) -> Result<Option<i64>, String> {
+    if word == "NEAR" {
+        return Ok(Some(NEAR_DISTANCE));
+    }
+    match word.strip_prefix("NEAR/") {
+        Some(n) => n
+            .parse::<u32>()
+            .map(|n| Some(n as i64))
+            .map_err(|_| format!("{} needs a number of words, like NEAR/5", word)),
+        None => Ok(None),
+    }
+}
+
+/// Recursive descent over the lexemes, one method per level of binding.
+struct Parser {
+    lexemes: Vec<(usize, Lexeme)>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.next).map(|(_, lexeme)| lexeme)
+    }
+    fn eat(&mut self, lexeme: &Lexeme) -> bool {
+        let found = self.peek() == Some(lexeme);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+    fn or(&mut self) -> Result<Query, String> {
+        let mut parts = vec![self.and()?];
+        while self.eat(&Lexeme::Or) {
+            parts.push(self.and()?);
+        }
+
+        Ok(one_or(parts, Query::Or))
+    }
+    fn and(&mut self) -> Result<Query, String> {
+        let mut parts = vec![self.not()?];
+        loop {
+            if self.eat(&Lexeme::And) {
+                parts.push(self.not()?);
+                continue;
+            }
+            match self.peek() {
+                Some(Lexeme::Close | Lexeme::Or | Lexeme::Near(_)) | None => break,
+                // next to each other is AND too
+                Some(_) => parts.push(self.not()?),
+            }
+        }
+
+        Ok(one_or(parts, Query::And))
+    }
+    fn not(&mut self) -> Result<Query, String> {
+        if self.eat(&Lexeme::Not) {
+            return Ok(Query::Not(Box::new(self.not()?)));
+        }
+        self.near()
+    }
+    fn near(&mut self) -> Result<Query, String> {
+        let mut left = self.primary()?;
+        while let Some(Lexeme::Near(distance)) = self.peek().cloned() {
+            let at = self.lexemes[self.next].0;
+            self.next += 1;
+            let right = self.primary()?;
+
+            let positional = |q: &Query| {
+                matches!(
+                    q,
+                    Query::Word(_) | Query::Phrase(_) | Query::Terms(_) | Query::Near(..)
+                )
+            };
+            if !positional(&left) || !positional(&right) {
+                return Err(format!(
+                    "NEAR at {} only joins words, phrases and patterns",
+                    at
+                ));
+            }
+            left = Query::Near(Box::new(left), Box::new(right), distance);
+        }
+
+        Ok(left)
+    }
+    fn primary(&mut self) -> Result<Query, String> {
+        let Some((at, lexeme)) = self.lexemes.get(self.next).cloned() else {
+            let (at, last) = &self.lexemes[self.next - 1];
+            return Err(format!("{} at {} needs something after it", last, at));
+        };
+        self.next += 1;
+
+        match lexeme {
+            Lexeme::Word(word) => Ok(Query::Word(word)),
+            Lexeme::Phrase(phrase) => Ok(Query::Phrase(phrase)),
+            Lexeme::Pattern(pattern) => Ok(Query::Terms(pattern)),
+            Lexeme::Field(field, value) => Ok(Query::Field(field, value)),
+            Lexeme::Open => {
+                let inner = self.or()?;
+                if !self.eat(&Lexeme::Close) {
+                    return Err(format!("( at {} is never closed", at));
+                }
+                Ok(inner)
+            }
+            Lexeme::Close => Err(format!("Unexpected ) at {}", at)),
+            operator => Err(format!("{} at {} needs something before it", operator, at)),
+        }
+    }
+}
+
+/// The only part, or the parts joined.
+fn one_or(mut parts: Vec<Query>, join: fn(Vec<Query>) -> Query) -> Query {
+    if parts.len() == 1 {
+        parts.pop().unwrap()
+    } else {
+        join(parts)
+    }
+}
+
+/// What a search looks in and how loosely it matches words.
+struct Scope<'a> {
+    similarity: f64,
+    libraries: &'a [i64],
+    stemmed: bool,
+    language: Option<&'a str>,
+    /// Words left out of the search.
+    stop: HashSet<String>,
+    /// What the user should know about the results.
+    warnings: RefCell<Vec<String>>,
+}
+
+/// Results of a search, best first.
+#[derive(Debug, Default)]
+pub struct Found {
+    pub hits: Vec<DictWord>,
+    /// Parts of the query that were only searched in part, like a wildcard
+    /// matching too many words.
+    pub warnings: Vec<String>,
+}
+
+impl Db {
+    /// Searches a query, see `parse`, best matches first. Words are searched
+    /// fuzzily and stop words among them left out in files of their language
+    /// unless the query has nothing else, phrases and `NEAR` groups are found
+    /// by the positions of their words. Files matched by fields or `NOT`
+    /// alone come last.
+    pub fn search(
+        &self,
+        query: &str,
+        similarity: f64,
+        libraries: &[i64],
+        stemmed: bool,
+        language: Option<&str>,
+    ) -> Result<Found, String> {
+        let Some(query) = parse(query)? else {
+            return Ok(Found::default());
+        };
+        let mut scope = Scope {
+            similarity,
+            libraries,
+            stemmed,
+            language,
+            stop: HashSet::new(),
+            warnings: RefCell::new(vec![]),
+        };
+
+        // files of each language are searched leaving out its own stop
+        // words, those of a language without any together
+        let mut stop = self.stop_word_sets(language);
+        let mut languages: HashMap<String, HashSet<i64>> = HashMap::new();
+        for (file_idx, file_language) in self.scope_files("language", &scope) {
+            let key = if stop.contains_key(&file_language) {
+                file_language
+            } else {
+                String::new()
+            };
+            languages.entry(key).or_default().insert(file_idx);
+        }
+        let mut matches = Matches::new();
+        for (file_language, files) in languages {
+            scope.stop = stop.remove(&file_language).unwrap_or_default();
+            let found = match self.matches(&query, &scope) {
+                Some(found) => found,
+                None => {
+                    scope.stop.clear();
+                    self.matches(&query, &scope).unwrap_or_default()
+                }
+            };
+            matches.extend(
+                found
+                    .into_iter()
+                    .filter(|(file_idx, _)| files.contains(file_idx)),
+            );
+        }
+
+        let content = query.to_string();
+        let mut hits = Hits::new(self, libraries, language);
+        let mut res: Vec<DictWord> = vec![];
+        for (file_idx, found) in matches {
+            if !found.is_empty() {
+                res.extend(found);
+            } else if let Some(hit) = hits.hit(file_idx, 0) {
+                res.push(DictWord {
+                    content: content.clone(),
+                    weight: 0.0,
+                    ..hit
+                });
+            }
+        }
+        res.sort_by(|a, b| b.score().partial_cmp(&a.score()).unwrap());
+
+        Ok(Found {
+            hits: collapse_duplicates(res),
+            warnings: scope.warnings.into_inner(),
+        })
+    }
+    /// Files a query matches, `None` for a query of stop words only, which
+    /// leaves the parts around it as they are.
+    fn matches(&self, query: &Query, scope: &Scope) -> Option<Matches> {
+        match query {
+            Query::Word(word) => {
+                let found: Vec<Matches> = tokens(word, &self.word_tokenizer())
+                    .iter()
+                    .filter(|t| !scope.stop.contains(&t.term))
+                    .map(|t| {
+                        by_file(self.search_word(
+                            t.word,
+                            scope.similarity,
+                            scope.similarity,
+                            scope.libraries,
+                            scope.stemmed,
+                            scope.language,
+                        ))
+                    })
+                    .collect();
+                all(found)
+            }
+            Query::Phrase(_) | Query::Near(..) => Some(by_file(self.search_spans(query, scope))),
+            Query::Terms(pattern) => {
+                let mut hits = Hits::new(self, scope.libraries, scope.language);
+                let mut res: Vec<DictWord> = vec![];
+                for (term_id, term) in self.expand(pattern, scope) {
+                    let weight = self.idf(term_id);
+                    for (file_idx, word_idx) in self.postings(term_id) {
+                        if let Some(hit) = hits.hit(file_idx, word_idx) {
+                            res.push(DictWord {
+                                content: term.clone(),
+                                weight,
+                                ..hit
+                            });
+                        }
+                    }
+                }
+                Some(by_file(res))
+            }
+            Query::Field(field, value) => {
+                // `path:legal/` also finds `C:\Shares\Legal\x.pdf`
+                let fold = |text: &str| match field {
+                    Field::Path => text.to_lowercase().replace('\\', "/"),
+                    Field::Type | Field::Author => text.to_lowercase(),
+                };
+                let value = fold(value);
+                let found = self
+                    .scope_files(field.column(), scope)
+                    .into_iter()
+                    .filter(|(_, column)| match field {
+                        Field::Type => fold(column) == value,
+                        Field::Path | Field::Author => fold(column).contains(&value),
+                    })
+                    .map(|(file_idx, _)| (file_idx, vec![]))
+                    .collect();
+                Some(found)
+            }
+            Query::Not(inner) => {
+                let inner = self.matches(inner, scope)?;
+                let found = self
+                    .scope_files("rowid", scope)
+                    .into_iter()
+                    .filter(|(file_idx, _)| !inner.contains_key(file_idx))
+                    .map(|(file_idx, _)| (file_idx, vec![]))
+                    .collect();
+                Some(found)
+            }
+            Query::And(parts) => all(parts
+                .iter()
+                .filter_map(|p| self.matches(p, scope))
+                .collect()),
+            Query::Or(parts) => {
+                let found: Vec<Matches> = parts
+                    .iter()
+                    .filter_map(|p| self.matches(p, scope))
+                    .collect();
+                if found.is_empty() {
+                    return None;
+                }
+                let mut res = Matches::new();
+                for matches in found {
+                    for (file_idx, hits) in matches {
+                        res.entry(file_idx).or_default().extend(hits);
+                    }
+                }
+                Some(res)
+            }
+        }
+    }
+    /// Terms of the dictionary a pattern matches in alphabetical order, at
+    /// most `EXPANSION_LIMIT` of them. Leaving some out adds a warning.
+    fn expand(&self, pattern: &Pattern, scope: &Scope) -> Vec<(i64, String)> {
+        let limit = EXPANSION_LIMIT + 1;
+        let read = |r: sqlite::Row| {
+            (
+                r.read::<i64, _>("rowid"),
+                r.read::<&str, _>("term").to_string(),
+            )
+        };
+        let mut terms: Vec<(i64, String)> = match pattern {
+            Pattern::Wildcard(wildcard) => {
+                let mut stat = self
+                    .pool
+                    .prepare(
+                        "SELECT rowid, term FROM term WHERE term GLOB ? ORDER BY term LIMIT ?;",
+                    )
+                    .unwrap();
+                stat.bind((1, self.glob(wildcard).as_str())).unwrap();
+                stat.bind((2, limit as i64)).unwrap();
+
+                stat.into_iter().map(|r| read(r.unwrap())).collect()
+            }
+            Pattern::Regex(regex) => {
+                // checked when the query was read
+                let regex = Regex::new(&format!("(?i)^(?:{})$", regex)).unwrap();
+
+                self.pool
+                    .prepare("SELECT rowid, term FROM term ORDER BY term;")
+                    .unwrap()
+                    .into_iter()
+                    .map(|r| read(r.unwrap()))
+                    .filter(|(_, term)| regex.is_match(term))
+                    .take(limit)
+                    .collect()
+            }
+        };
+
+        if terms.len() > EXPANSION_LIMIT {
+            terms.truncate(EXPANSION_LIMIT);
+            let warning = format!(
+                "{} matches more than {} words, only {} to {} were searched",
+                pattern,
+                EXPANSION_LIMIT,
+                terms[0].1,
+                terms[EXPANSION_LIMIT - 1].1
+            );
+            // a pattern is expanded once for the files of each language
+            let mut warnings = scope.warnings.borrow_mut();
+            if !warnings.contains(&warning) {
+                warnings.push(warning);
+            }
+        }
+
+        terms
+    }
+    /// A wildcard as an SQLite GLOB over the terms, its letters compared as
+    /// they are indexed.
+    fn glob(&self, wildcard: &str) -> String {
+        let options = self.word_tokenizer();
+        let mut res = String::new();
+        let mut letters = String::new();
+        for c in wildcard.chars() {
+            if c == '*' || c == '?' {
+                res.push_str(&normalize(&letters, &options).replace('[', "[[]"));
+                letters.clear();
+                res.push(c);
+            } else {
+                letters.push(c);
+            }
+        }
+        res.push_str(&normalize(&letters, &options).replace('[', "[[]"));
+
+        res
+    }
+    /// Live files the search looks in, with a column of theirs as text.
+    fn scope_files(&self, column: &str, scope: &Scope) -> Vec<(i64, String)> {
+        let query = format!(
+            "SELECT rowid, CAST(IFNULL({}, '') AS TEXT) AS value FROM file WHERE deleted=0 AND library_id IN ({}) AND language LIKE ?;",
+            column,
+            vec!["?"; scope.libraries.len()].join(", ")
+        );
+        let mut stat = self.pool.prepare(query).unwrap();
+        for (i, library) in scope.libraries.iter().enumerate() {
+            stat.bind((i + 1, *library)).unwrap();
+        }
+        stat.bind((scope.libraries.len() + 1, scope.language.unwrap_or("%")))
+            .unwrap();
+
+        stat.into_iter()
+            .map(|r| r.unwrap())
+            .map(|r| {
+                (
+                    r.read::<i64, _>("rowid"),
+                    r.read::<&str, _>("value").to_string(),
+                )
+            })
+            .collect()
+    }
+    /// Hits of a phrase or `NEAR` group, one at the start of each place it
+    /// matches. Weighted by the rarity of its words together.
+    fn search_spans(&self, part: &Query, scope: &Scope) -> Vec<DictWord> {
+        let mut terms: Vec<i64> = vec![];
+        let spans = self.spans(part, scope, &mut terms);
+        let weight: f32 = terms.iter().map(|t| self.idf(*t)).sum();
+        let content = part.to_string();
+
+        let mut hits = Hits::new(self, scope.libraries, scope.language);
+        let mut res: Vec<DictWord> = vec![];
+        for (file_idx, spans) in spans {
+            for (first, _) in spans {
+                if let Some(hit) = hits.hit(file_idx, first) {
+                    res.push(DictWord {
+                        content: content.clone(),
+                        weight,
+                        ..hit
+                    });
+                }
+            }
+        }
+
+        res
+    }
+    /// Where a part matches, a word alone being a phrase of one word. The
+    /// terms it was looked up by are added to `terms`.
+    fn spans(&self, part: &Query, scope: &Scope, terms: &mut Vec<i64>) -> Spans {
+        let (left, right, distance) = match part {
+            Query::Word(text) | Query::Phrase(text) => {
+                return self.phrase_spans(text, scope.stemmed, scope.language, terms)
+            }
+            Query::Terms(pattern) => {
+                let mut res = Spans::new();
+                for (term_id, _) in self.expand(pattern, scope) {
+                    terms.push(term_id);
+                    for (file_idx, position) in self.postings(term_id) {
+                        res.entry(file_idx).or_default().push((position, position));
+                    }
+                }
+                return res;
+            }
+            Query::Near(left, right, distance) => (left, right, *distance),
+            // the parser only lets words and phrases next to NEAR
+            _ => return Spans::new(),
+        };
+        let left = self.spans(left, scope, terms);
+        let right = self.spans(right, scope, terms);
+
+        let mut res = Spans::new();
+        for (file_idx, left) in left {
+            let Some(right) = right.get(&file_idx) else {
+                continue;
+            };
+            for a in left.iter() {
+                for b in right.iter() {
+                    // negative for parts that share a position
+                    let between = (b.0 - a.1).max(a.0 - b.1) - 1;
+                    if !(0..=distance).contains(&between) {
+                        continue;
+                    }
+                    let span = (a.0.min(b.0), a.1.max(b.1));
+                    let found = res.entry(file_idx).or_default();
+                    // `a NEAR a` finds each pair both ways round
+                    if !found.contains(&span) {
+                        found.push(span);
+                    }
+                }
+            }
+        }
+
+        res
+    }
+    /// Places the words of `text` follow each other. Stop words left out of
+    /// the index stand for any word in files of their language.
+    fn phrase_spans(
+        &self,
+        text: &str,
+        stemmed: bool,
+        language: Option<&str>,
+        terms: &mut Vec<i64>,
+    ) -> Spans {
+        let options = self.word_tokenizer();
+        let stop = if options.skip_stop_words {
+            self.stop_word_sets(language)
+        } else {
+            HashMap::new()
+        };
+        let words = tokens(text, &options);
+
+        // positions of each word by file, and the languages it is any word in
+        let mut positions: Vec<(HashSet<&str>, Positions)> = vec![];
+        for word in words.iter() {
+            let skipped = stop_in(&stop, &word.term);
+            let mut forms: Vec<i64> = self.find_term(&word.term).into_iter().collect();
+            if stemmed {
+                forms.extend(
+                    self.word_forms(&word.term, language)
+                        .into_iter()
+                        .map(|(id, _)| id),
+                );
+            }
+
+            let mut by_file = Positions::new();
+            for term_id in forms {
+                terms.push(term_id);
+                for (file_idx, position) in self.postings(term_id) {
+                    by_file.entry(file_idx).or_default().insert(position);
+                }
+            }
+            positions.push((skipped, by_file));
+        }
+
+        // each file is anchored at the first word that is not a stop word
+        // of its language
+        let files: HashSet<i64> = positions
+            .iter()
+            .flat_map(|(_, word)| word.keys().copied())
+            .collect();
+        let mut res = Spans::new();
+        let last = words.len() as i64 - 1;
+        for file_idx in files {
+            let file_language = if stop.is_empty() {
+                String::new()
+            } else {
+                self.get_file_idx(file_idx).language
+            };
+            let Some(anchor) = positions
+                .iter()
+                .position(|(skipped, _)| !skipped.contains(file_language.as_str()))
+            else {
+                continue;
+            };
+            let Some(found) = positions[anchor].1.get(&file_idx) else {
+                continue;
+            };
+            for position in found {
+                let first = position - anchor as i64;
+                let matches = positions.iter().enumerate().all(|(i, (skipped, word))| {
+                    skipped.contains(file_language.as_str())
+                        || word
+                            .get(&file_idx)
+                            .is_some_and(|found| found.contains(&(first + i as i64)))
+                });
+                if first >= 0 && matches {
+                    res.entry(file_idx).or_default().push((first, first + last));
+                }
+            }
+        }
+
+        res
+    }
+}
+
+/// Hits grouped by the file they are in.
+fn by_file(hits: Vec<DictWord>) -> Matches {
+    let mut res = Matches::new();
+    for hit in hits {
+        res.entry(hit.file_idx).or_default().push(hit);
+    }
+    res
+}
+
+/// Files every one of `found` has, with all their hits. `None` when there
+/// is nothing to match.
+fn all(found: Vec<Matches>) -> Option<Matches> {
+    let mut found = found.into_iter();
+    let mut res = found.next()?;
+    for matches in found {
+        res.retain(|file_idx, _| matches.contains_key(file_idx));
+        for (file_idx, hits) in matches {
+            if let Some(kept) = res.get_mut(&file_idx) {
+                kept.extend(hits);
+            }
+        }
+    }
+
+    Some(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A query as it reads back after parsing.
+    fn parsed(query: &str) -> String {
+        parse(query).unwrap().unwrap().to_string()
+    }
+
+    fn error(query: &str) -> String {
+        parse(query).unwrap_err()
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(parse("  ").unwrap(), None);
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(parsed("a b"), "a AND b");
+        assert_eq!(parsed("a b OR c"), "(a AND b) OR c");
+        assert_eq!(parsed("a OR b c"), "a OR (b AND c)");
+        assert_eq!(parsed("a b OR NOT c"), "(a AND b) OR NOT c");
+        assert_eq!(
+            parsed("a (b OR c) NOT (d e)"),
+            "a AND (b OR c) AND NOT (d AND e)"
+        );
+        assert_eq!(parsed("NOT NOT a"), "NOT NOT a");
+        assert_eq!(parsed("and or"), "and AND or");
+    }
+
+    #[test]
+    fn round_trip() {
+        for query in [
+            "(a AND b) OR c",
+            "a AND NOT (b OR c)",
+            "\"lazy dog\" AND fox",
+            "fox NEAR/3 \"lazy dog\"",
+            "path:legal/ AND author:\"A B\"",
+            "contr* OR /colou?r/",
+            "/a\\/b/",
+        ] {
+            assert_eq!(parsed(query), query);
+        }
+    }
+
+    #[test]
+    fn near() {
+        assert_eq!(parsed("a NEAR b"), "a NEAR/10 b");
+        assert_eq!(parsed("a NEAR/0 b c"), "a NEAR/0 b AND c");
+        assert_eq!(parsed("a NEAR/2 b NEAR/3 c"), "a NEAR/2 b NEAR/3 c");
+        assert_eq!(parsed("c?t NEAR \"x y\""), "c?t NEAR/10 \"x y\"");
+        assert_eq!(
+            error("(a OR b) NEAR c"),
+            "NEAR at 10 only joins words, phrases and patterns"
+        );
+        assert_eq!(
+            error("path:x NEAR c"),
+            "NEAR at 8 only joins words, phrases and patterns"
+        );
+        assert!(error("a NEAR/x b").contains("NEAR/x"));
+        assert!(error("a NEAR/-3 b").contains("NEAR/-3"));
+        assert!(parse("NEAR").is_err());
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(
+            parse("type:PDF").unwrap(),
+            Some(Query::Field(Field::Type, "PDF".to_string()))
+        );
+        assert_eq!(
+            parse("author:\"Ivan  Ivanov\" x").unwrap(),
+            Some(Query::And(vec![
+                Query::Field(Field::Author, "Ivan  Ivanov".to_string()),
+                Query::Word("x".to_string()),
+            ]))
+        );
+        assert_eq!(parsed("size:10"), "size:10");
+        assert_eq!(error("a type:"), "type: at 3 needs a value");
+        assert_eq!(error("author:\" \""), "Empty phrase at 8");
+        assert_eq!(error("author:\"open"), "Unclosed quote at 8");
+    }
+
+    #[test]
+    fn patterns() {
+        assert_eq!(
+            parse("c?nt*").unwrap(),
+            Some(Query::Terms(Pattern::Wildcard("c?nt*".to_string())))
+        );
+        assert_eq!(
+            parse("/a\\/b/").unwrap(),
+            Some(Query::Terms(Pattern::Regex("a/b".to_string())))
+        );
+        assert_eq!(error("x /lea"), "Unclosed regex at 3");
+        assert_eq!(error("//"), "Empty regex at 1");
+        assert_eq!(error("/(/"), "Bad regex at 1: unclosed group");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("\"open"), "Unclosed quote at 1");
+        assert_eq!(error("a \"\""), "Empty phrase at 3");
+        assert_eq!(error("(a"), "( at 1 is never closed");
+        assert_eq!(error("a)"), "Unexpected ) at 2");
+        assert_eq!(error("AND a"), "AND at 1 needs something before it");
+        assert_eq!(error("a OR"), "OR at 3 needs something after it");
+        // positions count characters, not bytes
+        assert_eq!(error("ёж )"), "Unexpected ) at 4");
+    }
+}
@@ -0,0 +1,95 @@
+use std::path::Path;
+
+use sqlite::Value;
+
+use crate::db::Db;
+
+impl Db {
+    /// Drops one indexed file. Returns the number of file rows removed.
+    pub fn remove_file(&self, file_idx: i64) -> i64 {
+        self.pool.execute("BEGIN IMMEDIATE;").unwrap();
+        let removed = self.remove_files("rowid=?", &[Value::Integer(file_idx)]);
+        self.pool.execute("COMMIT;").unwrap();
+
+        removed
+    }
+    /// Drops every file and folder indexed under `path` in a library. A folder
+    /// inside a root comes back with the next scan of the root.
+    pub fn remove_dir(&self, library_id: i64, path: &str) -> i64 {
+        self.pool.execute("BEGIN IMMEDIATE;").unwrap();
+        let removed = self.remove_under(library_id, path);
+        self.pool.execute("COMMIT;").unwrap();
+
+        removed
+    }
+    /// Removes a root from a library along with what was indexed under it and
+    /// its scan history.
+    pub fn remove_root(&self, library_id: i64, path: &str) -> i64 {
+        self.pool.execute("BEGIN IMMEDIATE;").unwrap();
+        let removed = self.remove_under(library_id, path);
+
+        for query in [
+            "DELETE FROM library_root WHERE library_id=? AND path=?;",
+            "DELETE FROM scan_run WHERE library_id=? AND root=?;",
+        ] {
+            let mut stat = self.pool.prepare(query).unwrap();
+            stat.bind((1, library_id)).unwrap();
+            stat.bind((2, path)).unwrap();
+            stat.next().expect("Err during removing root");
+        }
+        self.pool.execute("COMMIT;").unwrap();
+
+        removed
+    }
+    fn remove_under(&self, library_id: i64, path: &str) -> i64 {
+        let prefix = Path::new(path).join("");
+        let prefix = prefix.to_str().unwrap();
+
+        let mut stat = self
+            .pool
+            .prepare("DELETE FROM dir WHERE library_id=? AND (path=? OR substr(path, 1, length(?3))=?3);")
+            .unwrap();
+        stat.bind((1, library_id)).unwrap();
+        stat.bind((2, path)).unwrap();
+        stat.bind((3, prefix)).unwrap();
+        stat.next().expect("Err during removing folders");
+
+        self.remove_files(
+            "library_id=? AND substr(path, 1, length(?2))=?2",
+            &[
+                Value::Integer(library_id),
+                Value::String(prefix.to_string()),
+            ],
+        )
+    }
+    /// Deletes the files matching `condition`, tombstoned copies included,
+    /// with their postings, tokens, kept text and full-text rows. Terms left
+    /// without postings stay in the dictionary until it is compacted.
+    pub(crate) fn remove_files(&self, condition: &str, params: &[Value]) -> i64 {
+        let query = "
+	CREATE TEMP TABLE IF NOT EXISTS removed_file(file_id INTEGER PRIMARY KEY);
+	DELETE FROM removed_file;
+	";
+        self.pool.execute(query).unwrap();
+
+        let query = format!(
+            "INSERT INTO removed_file SELECT rowid FROM file WHERE {};",
+            condition
+        );
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind(params).unwrap();
+        stat.next().expect("Err during removing files");
+        let removed = self.pool.change_count() as i64;
+
+        let query = "
+	DELETE FROM posting WHERE file_id IN (SELECT file_id FROM removed_file);
+	DELETE FROM token WHERE file_id IN (SELECT file_id FROM removed_file);
+	DELETE FROM file_text WHERE file_id IN (SELECT file_id FROM removed_file);
+	DELETE FROM file_fts WHERE rowid IN (SELECT file_id FROM removed_file);
+	DELETE FROM file WHERE rowid IN (SELECT file_id FROM removed_file);
+	";
+        self.pool.execute(query).unwrap();
+
+        removed
+    }
+}
@@ -0,0 +1,392 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
+
+use chrono::{DateTime, Datelike, Duration, Local, TimeZone, Timelike};
+use sqlite::Row;
+
+use crate::db::{Db, ScanReport};
+use crate::library::Library;
+
+/// A cron-like schedule: `minute hour day-of-month month day-of-week`.
+///
+/// Every field takes `*`, a number, a range `a-b`, a step `*/n` or `a-b/n`
+/// and comma separated lists of those. `@hourly`, `@daily`, `@midnight` and
+/// `@weekly` are accepted as shortcuts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Cron matches either day field when both are restricted.
+    any_day: bool,
+}
+
+impl Schedule {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let expr = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            e => e,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("expected 5 fields, got {}", fields.len()));
+        }
+
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // both 0 and 7 are sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+
+        Ok(Self {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] != "*" && fields[4] != "*",
+        })
+    }
+    pub fn matches<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> bool {
+        let bit = |mask: u64, v: u32| mask & (1 << v) != 0;
+
+        let day = bit(self.days, time.day());
+        let weekday = bit(self.weekdays, time.weekday().num_days_from_sunday());
+        let day_ok = if self.any_day {
+            day || weekday
+        } else {
+            day && weekday
+        };
+
+        day_ok
+            && bit(self.minutes, time.minute())
+            && bit(self.hours, time.hour())
+            && bit(self.months, time.month())
+    }
+    /// Latest minute not after `now` and not before `since` the schedule fires.
+    pub fn last_due(
+        &self,
+        since: DateTime<Local>,
+        now: DateTime<Local>,
+    ) -> Option<DateTime<Local>> {
+        let mut time = now.with_second(0)?.with_nanosecond(0)?;
+
+        while time >= since {
+            if self.matches(&time) {
+                return Some(time);
+            }
+            time -= Duration::minutes(1);
+        }
+
+        None
+    }
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut mask = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((r, s)) => (r, parse_num(s, 1, max)?),
+            None => (part, 1),
+        };
+        let (from, to) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (parse_num(a, min, max)?, parse_num(b, min, max)?)
+        } else {
+            let v = parse_num(range, min, max)?;
+            // `5/15` means every 15 starting at 5
+            (v, if step > 1 { max } else { v })
+        };
+        if from > to {
+            return Err(format!("empty range `{}`", range));
+        }
+
+        for v in (from..=to).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+
+    Ok(mask)
+}
+
+fn parse_num(s: &str, min: u32, max: u32) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(v) if v >= min && v <= max => Ok(v),
+        _ => Err(format!(
+            "`{}` is not a number between {} and {}",
+            s, min, max
+        )),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanRun {
+    pub root: String,
+    /// Unix seconds.
+    pub started_at: i64,
+    pub duration_ms: i64,
+    pub indexed: i64,
+    pub skipped: i64,
+    pub removed: i64,
+    /// `running` until the scan finishes, then `ok`, or `failed` when it
+    /// stopped on an error. A run still `running` when the app starts was
+    /// interrupted.
+    pub status: String,
+    /// `manual` or `scheduled`.
+    pub trigger: String,
+}
+
+impl ScanRun {
+    pub fn started(&self) -> DateTime<Local> {
+        Local
+            .timestamp_opt(self.started_at, 0)
+            .single()
+            .unwrap_or_default()
+    }
+}
+
+impl Db {
+    /// Scans one root of a library and keeps a record of the run. A run the
+    /// app was closed in the middle of is picked up where it stopped, one
+    /// that stops on an error, like a share that is gone, is `failed`.
+    pub fn scan_root(&self, library: &Library, root: &str, trigger: &str) -> ScanRun {
+        let (run_id, mut run) = match self.unfinished_run(library.id, root) {
+            Some(unfinished) => unfinished,
+            None => self.start_run(library.id, root, trigger),
+        };
+
+        let timer = Instant::now();
+        let mut report = ScanReport {
+            indexed: run.indexed,
+            skipped: run.skipped,
+            removed: run.removed,
+        };
+        let scanned = panic::catch_unwind(AssertUnwindSafe(|| {
+            self.scan(library.id, root, &library.policy, run_id, &mut report)
+        }));
+        run.duration_ms += timer.elapsed().as_millis() as i64;
+        run.indexed = report.indexed;
+        run.skipped = report.skipped;
+        run.removed = report.removed;
+        run.status = if scanned.is_ok() {
+            "ok".to_string()
+        } else {
+            // the files of the transaction it stopped in are not indexed
+            let _ = self.pool.execute("ROLLBACK;");
+            "failed".to_string()
+        };
+
+        let query = "
+	UPDATE scan_run SET duration_ms=?, indexed=?, skipped=?, removed=?, status=? WHERE rowid=?;
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind((1, run.duration_ms)).unwrap();
+        stat.bind((2, run.indexed)).unwrap();
+        stat.bind((3, run.skipped)).unwrap();
+        stat.bind((4, run.removed)).unwrap();
+        stat.bind((5, run.status.as_str())).unwrap();
+        stat.bind((6, run_id)).unwrap();
+        stat.next().expect("Err during recording scan");
+
+        run
+    }
+    fn start_run(&self, library_id: i64, root: &str, trigger: &str) -> (i64, ScanRun) {
+        let started_at = Local::now().timestamp();
+        let query = "
+	INSERT INTO scan_run VALUES(?, ?, ?, 0, 0, 0, 0, 'running', ?) RETURNING rowid;
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind((1, library_id)).unwrap();
+        stat.bind((2, root)).unwrap();
+        stat.bind((3, started_at)).unwrap();
+        stat.bind((4, trigger)).unwrap();
+        stat.next().expect("Err during recording scan");
+        let run_id = stat.read::<i64, _>("rowid").unwrap();
+
+        let run = ScanRun {
+            root: root.to_string(),
+            started_at,
+            duration_ms: 0,
+            indexed: 0,
+            skipped: 0,
+            removed: 0,
+            status: "running".to_string(),
+            trigger: trigger.to_string(),
+        };
+        (run_id, run)
+    }
+    /// The last run of the root when it never finished.
+    fn unfinished_run(&self, library_id: i64, root: &str) -> Option<(i64, ScanRun)> {
+        let query = "
+	SELECT rowid, * FROM scan_run WHERE library_id=? AND root=? ORDER BY started_at DESC, rowid DESC LIMIT 1;
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind((1, library_id)).unwrap();
+        stat.bind((2, root)).unwrap();
+
+        stat.into_iter()
+            .map(|r| r.unwrap())
+            .next()
+            .filter(|r| r.read::<&str, _>("status") == "running")
+            .map(|r| (r.read::<i64, _>("rowid"), read_run(&r)))
+    }
+    /// Saves the counts of a run so far, in the transaction of the files
+    /// they count.
+    pub(crate) fn save_progress(&self, run_id: i64, report: &ScanReport) {
+        let query = "
+	UPDATE scan_run SET indexed=?, skipped=? WHERE rowid=?;
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind((1, report.indexed)).unwrap();
+        stat.bind((2, report.skipped)).unwrap();
+        stat.bind((3, run_id)).unwrap();
+        stat.next().expect("Err during recording scan");
+    }
+    pub fn last_run(&self, library_id: i64, root: &str) -> Option<ScanRun> {
+        let query = "
+	SELECT * FROM scan_run WHERE library_id=? AND root=? ORDER BY started_at DESC, rowid DESC LIMIT 1;
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind((1, library_id)).unwrap();
+        stat.bind((2, root)).unwrap();
+
+        stat.into_iter()
+            .map(|r| r.unwrap())
+            .next()
+            .map(|r| read_run(&r))
+    }
+    /// Roots whose schedule fired since their last run, looking back no
+    /// further than `since` so a new schedule does not fire for the past.
+    pub fn due_roots(&self, since: DateTime<Local>) -> Vec<(Library, String)> {
+        let now = Local::now();
+        let mut due = vec![];
+
+        for library in self.libraries() {
+            for root in library.roots.iter() {
+                let schedule = match Schedule::parse(&root.schedule) {
+                    Ok(s) if !root.schedule.trim().is_empty() => s,
+                    _ => continue,
+                };
+                // the minute after the last run started
+                let since = match self.last_run(library.id, &root.path) {
+                    Some(run) if run.started() > since => {
+                        run.started() + Duration::seconds(60 - run.started_at % 60)
+                    }
+                    _ => since,
+                };
+
+                if schedule.last_due(since, now).is_some() {
+                    due.push((library.clone(), root.path.clone()));
+                }
+            }
+        }
+
+        due
+    }
+}
+
+pub(crate) fn read_run(r: &Row) -> ScanRun {
+    ScanRun {
+        root: r.read::<&str, _>("root").to_string(),
+        started_at: r.read::<i64, _>("started_at"),
+        duration_ms: r.read::<i64, _>("duration_ms"),
+        indexed: r.read::<i64, _>("indexed"),
+        skipped: r.read::<i64, _>("skipped"),
+        removed: r.read::<i64, _>("removed"),
+        status: r.read::<&str, _>("status").to_string(),
+        trigger: r.read::<&str, _>("trigger").to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    /// Values a field allows.
+    fn values(mask: u64) -> Vec<u32> {
+        (0..64).filter(|v| mask & (1 << v) != 0).collect()
+    }
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+    }
+
+    #[test]
+    fn steps_and_ranges() {
+        let s = Schedule::parse("*/15 1-10/3 5/10 1,6-7 *").unwrap();
+        assert_eq!(values(s.minutes), [0, 15, 30, 45]);
+        assert_eq!(values(s.hours), [1, 4, 7, 10]);
+        assert_eq!(values(s.days), [5, 15, 25]);
+        assert_eq!(values(s.months), [1, 6, 7]);
+        assert_eq!(values(s.weekdays), (0..=7).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn shortcuts() {
+        assert_eq!(Schedule::parse("@daily"), Schedule::parse("0 0 * * *"));
+        assert_eq!(Schedule::parse("@midnight"), Schedule::parse("0 0 * * *"));
+        assert_eq!(Schedule::parse(" @hourly "), Schedule::parse("0 * * * *"));
+        assert_eq!(Schedule::parse("@weekly"), Schedule::parse("0 0 * * 0"));
+    }
+
+    #[test]
+    fn sunday_is_0_and_7() {
+        let sunday = at(2024, 6, 2, 3, 0);
+        assert!(Schedule::parse("0 3 * * 7").unwrap().matches(&sunday));
+        assert!(Schedule::parse("0 3 * * 0").unwrap().matches(&sunday));
+        assert!(!Schedule::parse("0 3 * * 1-6").unwrap().matches(&sunday));
+    }
+
+    #[test]
+    fn either_day_field() {
+        // the 13th or any friday
+        let s = Schedule::parse("0 0 13 * 5").unwrap();
+        assert!(s.matches(&at(2024, 6, 13, 0, 0)));
+        assert!(s.matches(&at(2024, 6, 7, 0, 0)));
+        assert!(!s.matches(&at(2024, 6, 8, 0, 0)));
+        // only one restricted, both have to match
+        let s = Schedule::parse("0 0 * * 5").unwrap();
+        assert!(!s.matches(&at(2024, 6, 13, 0, 0)));
+        let s = Schedule::parse("0 0 13 * *").unwrap();
+        assert!(!s.matches(&at(2024, 6, 7, 0, 0)));
+    }
+
+    #[test]
+    fn last_due() {
+        let s = Schedule::parse("30 2 * * *").unwrap();
+        let now = Local.with_ymd_and_hms(2024, 6, 2, 10, 0, 45).unwrap();
+        let due = Local.with_ymd_and_hms(2024, 6, 2, 2, 30, 0).unwrap();
+        assert_eq!(s.last_due(now - Duration::days(1), now), Some(due));
+        assert_eq!(s.last_due(due + Duration::minutes(1), now), None);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Schedule::parse("* * * *"),
+            Err("expected 5 fields, got 4".to_string())
+        );
+        assert_eq!(
+            Schedule::parse("60 * * * *"),
+            Err("`60` is not a number between 0 and 59".to_string())
+        );
+        assert_eq!(
+            Schedule::parse("* * 0 * *"),
+            Err("`0` is not a number between 1 and 31".to_string())
+        );
+        assert_eq!(
+            Schedule::parse("* 5-1 * * *"),
+            Err("empty range `5-1`".to_string())
+        );
+        assert!(Schedule::parse("*/0 * * * *").is_err());
+        assert!(Schedule::parse("a * * * *").is_err());
+    }
+}
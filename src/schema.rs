@@ -0,0 +1,241 @@
+use sqlite::Connection;
+
+use crate::stopwords;
+
+/// Schema changes in the order they were made. The database remembers how
+/// many of them it has gone through in `schema_version`, so opening it only
+/// runs the newer ones and an existing index is upgraded in place.
+const MIGRATIONS: [fn(&Connection); 10] = [
+    initial,
+    libraries_and_index,
+    unique_paths,
+    source_text,
+    extraction_errors,
+    term_stems,
+    stop_words,
+    file_languages,
+    file_authors,
+    ole_types,
+];
+
+pub fn migrate(conn: &Connection) {
+    // an index that is up to date is only read, a background scan may be
+    // holding the write lock
+    if has_table(conn, "schema_version") && current_version(conn) >= latest_version() {
+        return;
+    }
+    conn.execute("CREATE TABLE IF NOT EXISTS schema_version(version INTEGER);")
+        .unwrap();
+
+    for (idx, migration) in MIGRATIONS.iter().enumerate() {
+        let version = idx as i64 + 1;
+
+        // another instance may be migrating too, the write lock decides
+        conn.execute("BEGIN IMMEDIATE;").unwrap();
+        if current_version(conn) >= version {
+            conn.execute("COMMIT;").unwrap();
+            continue;
+        }
+
+        migration(conn);
+
+        conn.execute("DELETE FROM schema_version;").unwrap();
+        {
+            let mut stat = conn
+                .prepare("INSERT INTO schema_version VALUES(?);")
+                .unwrap();
+            stat.bind((1, version)).unwrap();
+            stat.next().expect("Err during saving schema version");
+        }
+        conn.execute("COMMIT;").unwrap();
+    }
+}
+
+/// Version a database has once every migration ran.
+pub fn latest_version() -> i64 {
+    MIGRATIONS.len() as i64
+}
+
+pub fn current_version(conn: &Connection) -> i64 {
+    conn.prepare("SELECT version FROM schema_version;")
+        .unwrap()
+        .into_iter()
+        .map(|r| r.unwrap())
+        .next()
+        .map(|r| r.read::<i64, _>("version"))
+        .unwrap_or(0)
+}
+
+fn has_table(conn: &Connection, table: &str) -> bool {
+    let mut stat = conn
+        .prepare("SELECT 1 FROM sqlite_master WHERE type='table' AND name=?;")
+        .unwrap();
+    stat.bind((1, table)).unwrap();
+
+    stat.into_iter().next().is_some()
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> bool {
+    conn.prepare(format!("PRAGMA table_info({});", table))
+        .unwrap()
+        .into_iter()
+        .map(|r| r.unwrap())
+        .any(|r| r.read::<&str, _>("name") == column)
+}
+
+/// Adds a column unless a build from before versioning already did.
+fn add_column(conn: &Connection, table: &str, column: &str, decl: &str) {
+    if !has_column(conn, table, column) {
+        conn.execute(format!(
+            "ALTER TABLE {} ADD COLUMN {} {};",
+            table, column, decl
+        ))
+        .unwrap();
+    }
+}
+
+/// The tables the first release shipped with.
+fn initial(conn: &Connection) {
+    let query = "
+	CREATE TABLE IF NOT EXISTS file(file_name VARCHAR(36), file_type VARCHAR(10), path TEXT, content TEXT);
+	CREATE TABLE IF NOT EXISTS dir(dir_name VARCHAR(36), path TEXT);
+	";
+    conn.execute(query).unwrap();
+}
+
+/// Libraries, scan history and the inverted index. Files indexed by older
+/// builds move to the default library and get re-indexed by the next scan
+/// as they have no modification time.
+fn libraries_and_index(conn: &Connection) {
+    add_column(conn, "file", "extension", "VARCHAR(10) DEFAULT ''");
+    add_column(conn, "file", "mime", "VARCHAR(80) DEFAULT ''");
+    add_column(conn, "file", "content_hash", "VARCHAR(64) DEFAULT ''");
+    add_column(conn, "file", "text_hash", "VARCHAR(64) DEFAULT ''");
+    add_column(conn, "file", "library_id", "INTEGER");
+    add_column(conn, "file", "mtime", "INTEGER DEFAULT 0");
+    add_column(conn, "file", "deleted", "INTEGER DEFAULT 0");
+    add_column(conn, "file", "seen_run", "INTEGER");
+    add_column(conn, "dir", "library_id", "INTEGER");
+
+    let query = "
+	CREATE TABLE IF NOT EXISTS library(name VARCHAR(36) UNIQUE, follow_symlinks INTEGER, same_filesystem INTEGER, dedupe_hardlinks INTEGER);
+	CREATE TABLE IF NOT EXISTS library_root(library_id INTEGER, path TEXT, schedule TEXT);
+	CREATE TABLE IF NOT EXISTS scan_run(library_id INTEGER, root TEXT, started_at INTEGER, duration_ms INTEGER, indexed INTEGER, skipped INTEGER, removed INTEGER, status VARCHAR(10), trigger VARCHAR(10));
+	CREATE TABLE IF NOT EXISTS term(term TEXT UNIQUE);
+	CREATE TABLE IF NOT EXISTS posting(term_id INTEGER, file_id INTEGER, position INTEGER);
+	CREATE INDEX IF NOT EXISTS posting_term ON posting(term_id);
+	CREATE INDEX IF NOT EXISTS posting_file ON posting(file_id);
+	CREATE TABLE IF NOT EXISTS term_gram(gram TEXT, term_id INTEGER);
+	CREATE INDEX IF NOT EXISTS term_gram_gram ON term_gram(gram);
+	CREATE TABLE IF NOT EXISTS setting(key TEXT UNIQUE, value TEXT);
+	INSERT INTO library SELECT 'Default', 0, 0, 1 WHERE NOT EXISTS (SELECT 1 FROM library);
+	UPDATE file SET library_id=(SELECT min(rowid) FROM library) WHERE library_id IS NULL;
+	UPDATE dir SET library_id=(SELECT min(rowid) FROM library) WHERE library_id IS NULL;
+	";
+    conn.execute(query).unwrap();
+}
+
+/// One live row per path. Older builds appended a new row on every scan,
+/// only the latest of those is kept.
+fn unique_paths(conn: &Connection) {
+    let query = "
+	UPDATE file SET deleted=1 WHERE deleted=0 AND rowid NOT IN (
+		SELECT max(rowid) FROM file WHERE deleted=0 GROUP BY library_id, path);
+	DELETE FROM dir WHERE rowid NOT IN (SELECT min(rowid) FROM dir GROUP BY library_id, path);
+	CREATE UNIQUE INDEX IF NOT EXISTS file_path ON file(library_id, path) WHERE deleted=0;
+	CREATE UNIQUE INDEX IF NOT EXISTS dir_path ON dir(library_id, path);
+	CREATE INDEX IF NOT EXISTS file_content_hash ON file(content_hash);
+	CREATE INDEX IF NOT EXISTS file_text_hash ON file(text_hash);
+	CREATE INDEX IF NOT EXISTS library_root_library ON library_root(library_id);
+	";
+    conn.execute(query).unwrap();
+}
+
+/// The extracted text as it was, compressed, and where in it each indexed
+/// word is. Files indexed before keep showing snippets of the filtered text.
+fn source_text(conn: &Connection) {
+    let query = "
+	CREATE TABLE IF NOT EXISTS file_text(file_id INTEGER PRIMARY KEY, text BLOB);
+	CREATE TABLE IF NOT EXISTS token(file_id INTEGER, position INTEGER, start_byte INTEGER, end_byte INTEGER);
+	CREATE INDEX IF NOT EXISTS token_file ON token(file_id, position);
+	";
+    conn.execute(query).unwrap();
+}
+
+/// Files whose text couldn't be extracted, so they can be told apart from
+/// files without text.
+fn extraction_errors(conn: &Connection) {
+    add_column(conn, "file", "extract_failed", "INTEGER DEFAULT 0");
+}
+
+/// Snowball stems next to the terms, empty while stemming is off. Filled for
+/// the existing terms on the next start.
+fn term_stems(conn: &Connection) {
+    add_column(conn, "term", "stem", "TEXT DEFAULT ''");
+    conn.execute("CREATE INDEX IF NOT EXISTS term_stem ON term(stem);")
+        .unwrap();
+}
+
+/// Editable stop-word lists, starting with the built-in ones.
+fn stop_words(conn: &Connection) {
+    conn.execute("CREATE TABLE IF NOT EXISTS stop_word(language TEXT, word TEXT);")
+        .unwrap();
+    add_stop_words(conn);
+}
+
+/// The language detected for each file, filled for the files indexed before
+/// on the next start. Languages added since get their stop words.
+fn file_languages(conn: &Connection) {
+    add_column(conn, "file", "language", "TEXT DEFAULT ''");
+    conn.execute("CREATE INDEX IF NOT EXISTS file_language ON file(language);")
+        .unwrap();
+    add_stop_words(conn);
+}
+
+/// The author a file names in its properties, NULL until the file is read
+/// again. The next scan reads it for the files indexed before.
+fn file_authors(conn: &Connection) {
+    add_column(conn, "file", "author", "TEXT");
+}
+
+/// Excel, PowerPoint and Outlook files were all taken for Word documents,
+/// they get the type their extension tells.
+fn ole_types(conn: &Connection) {
+    let query = "
+	UPDATE file SET
+		file_type = CASE
+			WHEN extension IN ('xls', 'xlt') THEN 'xls'
+			WHEN extension IN ('ppt', 'pps', 'pot') THEN 'ppt'
+			WHEN extension = 'msg' THEN 'msg'
+			ELSE 'ole' END,
+		mime = CASE
+			WHEN extension IN ('xls', 'xlt') THEN 'application/vnd.ms-excel'
+			WHEN extension IN ('ppt', 'pps', 'pot') THEN 'application/vnd.ms-powerpoint'
+			WHEN extension = 'msg' THEN 'application/vnd.ms-outlook'
+			ELSE 'application/x-ole-storage' END
+		WHERE mime='application/msword' AND extension NOT IN ('doc', 'dot');
+	";
+    conn.execute(query).unwrap();
+}
+
+/// Built-in stop words of the languages that have none yet.
+fn add_stop_words(conn: &Connection) {
+    let mut known = conn
+        .prepare("SELECT 1 FROM stop_word WHERE language=? LIMIT 1;")
+        .unwrap();
+    let mut stat = conn.prepare("INSERT INTO stop_word VALUES(?, ?);").unwrap();
+    for (language, words) in stopwords::LANGUAGES {
+        known.reset().unwrap();
+        known.bind((1, language)).unwrap();
+        if known.next().unwrap() == sqlite::State::Row {
+            continue;
+        }
+
+        for word in words.split_whitespace() {
+            stat.reset().unwrap();
+            stat.bind((1, language)).unwrap();
+            stat.bind((2, word)).unwrap();
+            stat.next().unwrap();
+        }
+    }
+}
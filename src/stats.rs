@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+
+use crate::db::Db;
+use crate::schedule::{read_run, ScanRun};
+
+/// Terms listed in `Stats::top_terms`.
+const TOP_TERMS: usize = 10;
+
+/// What the index holds, counting live files only.
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    pub files: i64,
+    pub dirs: i64,
+    /// `(type, files)`, most common first.
+    pub by_type: Vec<(String, i64)>,
+    /// `(language, files)` of the files whose language was told, most common
+    /// first.
+    pub by_language: Vec<(String, i64)>,
+    /// Indexed words, repeats included, as of the last scan or import like
+    /// the two below.
+    pub terms: i64,
+    pub unique_terms: i64,
+    /// `(term, occurrences)`, most frequent first, stop words left out.
+    pub top_terms: Vec<(String, i64)>,
+    pub size_on_disk: i64,
+    /// Paths of files whose text couldn't be extracted.
+    pub failed: Vec<String>,
+    pub last_scan: Option<ScanRun>,
+}
+
+impl Db {
+    pub fn stats(&self) -> Stats {
+        let rows = |query: &str| {
+            self.pool
+                .prepare(query)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.unwrap())
+                .collect::<Vec<_>>()
+        };
+        let count = |query: &str| {
+            rows(query)
+                .first()
+                .map(|r| r.read::<i64, _>("count"))
+                .unwrap_or(0)
+        };
+
+        let (terms, unique_terms, top_terms) = self.term_counts();
+
+        Stats {
+            files: count("SELECT count(*) AS count FROM file WHERE deleted=0;"),
+            dirs: count("SELECT count(*) AS count FROM dir;"),
+            by_type: rows(
+                "SELECT file_type, count(*) AS count FROM file WHERE deleted=0 GROUP BY file_type ORDER BY count DESC, file_type;",
+            )
+            .iter()
+            .map(|r| {
+                (
+                    r.read::<&str, _>("file_type").to_string(),
+                    r.read::<i64, _>("count"),
+                )
+            })
+            .collect(),
+            by_language: rows(
+                "SELECT language, count(*) AS count FROM file WHERE deleted=0 AND language!='' GROUP BY language ORDER BY count DESC, language;",
+            )
+            .iter()
+            .map(|r| {
+                (
+                    r.read::<&str, _>("language").to_string(),
+                    r.read::<i64, _>("count"),
+                )
+            })
+            .collect(),
+            terms,
+            unique_terms,
+            top_terms,
+            size_on_disk: self.size_on_disk(),
+            failed: rows("SELECT path FROM file WHERE deleted=0 AND extract_failed=1 ORDER BY path;")
+                .iter()
+                .map(|r| r.read::<&str, _>("path").to_string())
+                .collect(),
+            last_scan: rows("SELECT * FROM scan_run ORDER BY started_at DESC, rowid DESC LIMIT 1;")
+                .first()
+                .map(read_run),
+        }
+    }
+    /// Counts the words of the live files and the most frequent ones other
+    /// than stop words, kept for `stats` until the next scan or import.
+    /// Goes through every posting.
+    pub fn count_terms(&self) {
+        let query = "
+	SELECT count(*) AS total, count(DISTINCT term_id) AS uniq FROM posting
+	WHERE file_id IN (SELECT rowid FROM file WHERE deleted=0);
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.next().unwrap();
+        let mut counts = format!(
+            "{}\t{}",
+            stat.read::<i64, _>("total").unwrap(),
+            stat.read::<i64, _>("uniq").unwrap()
+        );
+
+        let stop: HashSet<String> = self.stop_word_sets(None).into_values().flatten().collect();
+        let query = "
+	SELECT term, count(*) AS count FROM posting JOIN term ON term.rowid = posting.term_id
+	WHERE file_id IN (SELECT rowid FROM file WHERE deleted=0)
+	GROUP BY term_id ORDER BY count DESC, term;
+	";
+        let top = self
+            .pool
+            .prepare(query)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap())
+            .map(|r| {
+                (
+                    r.read::<&str, _>("term").to_string(),
+                    r.read::<i64, _>("count"),
+                )
+            })
+            .filter(|(term, _)| !stop.contains(term))
+            .take(TOP_TERMS);
+        for (term, count) in top {
+            counts.push_str(&format!("\n{}\t{}", term, count));
+        }
+
+        self.set_setting("term_counts", &counts);
+    }
+    /// `(terms, unique_terms, top_terms)` as last counted, counted now for an
+    /// index that was never counted.
+    fn term_counts(&self) -> (i64, i64, Vec<(String, i64)>) {
+        let counts = match self.setting("term_counts") {
+            Some(counts) => counts,
+            None => {
+                self.count_terms();
+                self.setting("term_counts").unwrap_or_default()
+            }
+        };
+        let mut lines = counts.lines().map(|line| {
+            let (name, count) = line.split_once('\t').unwrap_or((line, "0"));
+            (name.to_string(), count.parse::<i64>().unwrap_or(0))
+        });
+        let (terms, unique_terms) = match lines.next() {
+            Some((terms, unique_terms)) => (terms.parse().unwrap_or(0), unique_terms),
+            None => (0, 0),
+        };
+
+        (terms, unique_terms, lines.collect())
+    }
+}
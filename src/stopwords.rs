@@ -0,0 +1,158 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::db::Db;
+use crate::text::normalize;
+
+/// Languages with a stop-word list and the words each starts with, common
+/// words that say little about what a text is about.
+pub const LANGUAGES: [(&str, &str); 3] = [
+    (
+        "english",
+        "a about above after again against all am an and any are as at be because been \
+         before being below between both but by can did do does doing down during each few \
+         for from further had has have having he her here hers herself him himself his how i \
+         if in into is it its itself just me more most my myself no nor not now of off on \
+         once only or other our ours ourselves out over own same she should so some such \
+         than that the their theirs them themselves then there these they this those through \
+         to too under until up very was we were what when where which while who whom why \
+         will with you your yours yourself yourselves",
+    ),
+    (
+        "russian",
+        "и в во не что он на я с со как а то все она так его но да ты к у же вы за бы по \
+         только ее мне было вот от меня еще нет о из ему теперь когда даже ну вдруг ли если \
+         уже или ни быть был него до вас нибудь опять уж вам ведь там потом себя ничего ей \
+         может они тут где есть надо ней для мы тебя их чем была сам чтоб без будто чего раз \
+         тоже себе под будет ж тогда кто этот того потому этого какой совсем ним здесь этом \
+         один почти мой тем чтобы нее сейчас были куда зачем всех никогда можно при наконец \
+         два об другой хоть после над больше тот через эти нас про всего них какая много \
+         разве три эту моя впрочем хорошо свою этой перед иногда лучше чуть том нельзя такой \
+         им более всегда конечно всю между",
+    ),
+    (
+        "german",
+        "aber alle allem allen aller alles als also am an ander andere anderem anderen \
+         anderer anderes anderm andern anders auch auf aus bei bin bis bist da damit dann \
+         das dass dasselbe dazu dein deine deinem deinen deiner dem demselben den denn \
+         denselben der derer derselbe derselben des desselben dessen dich die dies diese \
+         dieselbe dieselben diesem diesen dieser dieses dir doch dort du durch ein eine \
+         einem einen einer eines einig einige einigem einigen einiger einiges einmal er es \
+         etwas euch euer eure eurem euren eurer für gegen gewesen hab habe haben hat hatte \
+         hatten hier hin hinter ich ihm ihn ihnen ihr ihre ihrem ihren ihrer ihres im in \
+         indem ins ist jede jedem jeden jeder jedes jene jenem jenen jener jenes jetzt kann \
+         kein keine keinem keinen keiner keines können könnte machen man manche manchem \
+         manchen mancher manches mein meine meinem meinen meiner meines mich mir mit muss \
+         musste nach nicht nichts noch nun nur ob oder ohne sehr sein seine seinem seinen \
+         seiner seines selbst sich sie sind so solche solchem solchen solcher solches soll \
+         sollte sondern sonst über um und uns unsere unserem unseren unser unseres unter \
+         viel vom von vor während war waren warst was weg weil weiter welche welchem \
+         welchen welcher welches wenn werde werden wie wieder will wir wird wirst wo wollen \
+         wollte würde würden zu zum zur zwar zwischen",
+    ),
+];
+
+/// Languages among `sets` a term is a stop word of, left out of the search
+/// in files of those languages only.
+pub fn stop_in<'a>(sets: &'a HashMap<String, HashSet<String>>, term: &str) -> HashSet<&'a str> {
+    sets.iter()
+        .filter(|(_, words)| words.contains(term))
+        .map(|(language, _)| language.as_str())
+        .collect()
+}
+
+impl Db {
+    /// Stop words of a language, in the order they were saved.
+    pub fn stop_words(&self, language: &str) -> Vec<String> {
+        let mut stat = self
+            .pool
+            .prepare("SELECT word FROM stop_word WHERE language=? ORDER BY rowid;")
+            .unwrap();
+        stat.bind((1, language)).unwrap();
+
+        stat.into_iter()
+            .map(|r| r.unwrap().read::<&str, _>("word").to_string())
+            .collect()
+    }
+    /// Replaces the stop words of a language with the whitespace separated
+    /// `words`. The index is rebuilt when it leaves stop words out, returns
+    /// the number of files re-indexed.
+    pub fn set_stop_words(&self, language: &str, words: &str) -> i64 {
+        self.pool.execute("BEGIN IMMEDIATE;").unwrap();
+        let mut stat = self
+            .pool
+            .prepare("DELETE FROM stop_word WHERE language=?;")
+            .unwrap();
+        stat.bind((1, language)).unwrap();
+        stat.next().unwrap();
+
+        let mut stat = self
+            .pool
+            .prepare("INSERT INTO stop_word VALUES(?, ?);")
+            .unwrap();
+        for word in words.split_whitespace() {
+            stat.reset().unwrap();
+            stat.bind((1, language)).unwrap();
+            stat.bind((2, word)).unwrap();
+            stat.next().unwrap();
+        }
+        self.pool.execute("COMMIT;").unwrap();
+
+        if self.word_tokenizer().skip_stop_words {
+            self.retokenize()
+        } else {
+            0
+        }
+    }
+    /// Stop words of a language compared as terms are indexed.
+    pub fn stop_word_set(&self, language: &str) -> HashSet<String> {
+        let options = self.word_tokenizer();
+        let mut stat = self
+            .pool
+            .prepare("SELECT word FROM stop_word WHERE language=?;")
+            .unwrap();
+        stat.bind((1, language)).unwrap();
+
+        stat.into_iter()
+            .map(|r| normalize(r.unwrap().read::<&str, _>("word"), &options))
+            .collect()
+    }
+    /// Stop words by language, of `language` or of every language that has
+    /// them for `None`. A stop word of one language is a plain word in the
+    /// others, `war` or `die` among them, see `stop_in`.
+    pub fn stop_word_sets(&self, language: Option<&str>) -> HashMap<String, HashSet<String>> {
+        let languages: Vec<String> = self
+            .pool
+            .prepare("SELECT DISTINCT language FROM stop_word;")
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap().read::<&str, _>("language").to_string())
+            .filter(|stop| language.is_none_or(|language| stop == language))
+            .collect();
+
+        languages
+            .into_iter()
+            .map(|language| {
+                let words = self.stop_word_set(&language);
+                (language, words)
+            })
+            .collect()
+    }
+    /// Inverse document frequency of a term among the live files, the rarer
+    /// the term the higher. Never below zero, so a word in every file still
+    /// counts for a little.
+    pub fn idf(&self, term_id: i64) -> f32 {
+        let query = "
+	SELECT
+		(SELECT count(*) FROM file WHERE deleted=0) AS files,
+		(SELECT count(DISTINCT file_id) FROM posting JOIN file ON file.rowid = posting.file_id
+			WHERE term_id=? AND file.deleted=0) AS count;
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind((1, term_id)).unwrap();
+        stat.next().unwrap();
+        let files = stat.read::<i64, _>("files").unwrap() as f32;
+        let count = stat.read::<i64, _>("count").unwrap() as f32;
+
+        (1.0 + (files - count + 0.5) / (count + 0.5)).ln()
+    }
+}
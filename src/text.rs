@@ -0,0 +1,587 @@
+use std::io::{Read, Write};
+use std::sync::LazyLock;
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use rust_stemmers::{Algorithm, Stemmer};
+use sqlite::Value;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::db::Db;
+
+/// Bumped when `tokens` or `normalize` change, the kept texts are tokenized
+/// again on the next start.
+const TOKENIZER_VERSION: i64 = 4;
+
+/// How text is cut into words, for indexing and for queries alike.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenizerOptions {
+    /// `e-mail` stays one word instead of `e` and `mail`.
+    pub hyphens: bool,
+    /// `don't` stays one word instead of `don` and `t`.
+    pub apostrophes: bool,
+    /// Index numbers and words made of digits.
+    pub numbers: bool,
+    /// Index Chinese and Japanese ideographs as overlapping pairs, which
+    /// finds two-character words better than single characters do.
+    pub cjk_bigrams: bool,
+    /// `café` and `cafe` are the same word.
+    pub diacritics: bool,
+    /// Keep the stem of English, Russian and German words next to the word,
+    /// so a search can match other forms of it.
+    pub stemming: bool,
+    /// Leave stop words out of the postings. They are kept by default, word
+    /// searches skip them anyway and phrases need them.
+    pub skip_stop_words: bool,
+}
+
+impl Default for TokenizerOptions {
+    fn default() -> Self {
+        Self {
+            hyphens: false,
+            apostrophes: true,
+            numbers: true,
+            cjk_bigrams: true,
+            diacritics: false,
+            stemming: true,
+            skip_stop_words: false,
+        }
+    }
+}
+
+impl TokenizerOptions {
+    /// The enabled options as a comma separated list, as stored in settings.
+    pub fn to_setting(self) -> String {
+        let flags = [
+            ("hyphens", self.hyphens),
+            ("apostrophes", self.apostrophes),
+            ("numbers", self.numbers),
+            ("cjk_bigrams", self.cjk_bigrams),
+            ("diacritics", self.diacritics),
+            ("stemming", self.stemming),
+            ("skip_stop_words", self.skip_stop_words),
+        ];
+        flags
+            .iter()
+            .filter(|(_, on)| *on)
+            .map(|(name, _)| *name)
+            .collect::<Vec<&str>>()
+            .join(",")
+    }
+    pub fn from_setting(setting: &str) -> Self {
+        let on = |name: &str| setting.split(',').any(|f| f == name);
+        Self {
+            hyphens: on("hyphens"),
+            apostrophes: on("apostrophes"),
+            numbers: on("numbers"),
+            cjk_bigrams: on("cjk_bigrams"),
+            diacritics: on("diacritics"),
+            stemming: on("stemming"),
+            skip_stop_words: on("skip_stop_words"),
+        }
+    }
+}
+
+/// A word of the extracted text. `start..end` are its bytes in the text.
+#[derive(Debug, Clone)]
+pub struct Token<'a> {
+    /// The word as it is written.
+    pub word: &'a str,
+    /// The word as it is indexed and looked up, see `normalize`.
+    pub term: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The words of a text by Unicode word boundaries (UAX #29), in order. Token
+/// `i` is at position `i`.
+pub fn tokens<'a>(text: &'a str, options: &TokenizerOptions) -> Vec<Token<'a>> {
+    let mut words: Vec<Token> = vec![];
+
+    for (start, word) in text.unicode_word_indices() {
+        let token = Token {
+            word,
+            term: String::new(),
+            start,
+            end: start + word.len(),
+        };
+        if options.apostrophes {
+            words.push(token);
+        } else {
+            words.extend(split_apostrophes(text, token));
+        }
+    }
+
+    if options.hyphens {
+        words = join_hyphens(text, words);
+    }
+    if !options.numbers {
+        words.retain(|t| t.word.chars().any(|c| c.is_alphabetic()));
+    }
+    if options.cjk_bigrams {
+        words = cjk_bigrams(text, words);
+    }
+    for token in words.iter_mut() {
+        token.term = normalize(token.word, options);
+    }
+
+    words
+}
+
+/// NFKC, then case folding, `ё` as `е` and with `diacritics` the accents
+/// taken off. `й` keeps its breve, it is a letter of its own.
+pub fn normalize(word: &str, options: &TokenizerOptions) -> String {
+    let mut folded = String::new();
+
+    for c in word.nfkc() {
+        match c {
+            'ß' | 'ẞ' => folded.push_str("ss"),
+            'ς' => folded.push('σ'),
+            'ё' | 'Ё' => folded.push('е'),
+            c => folded.extend(c.to_lowercase()),
+        }
+    }
+    if !options.diacritics {
+        return folded;
+    }
+
+    let mut bare = String::new();
+    for c in folded.chars() {
+        if c == 'й' {
+            bare.push(c);
+        } else {
+            bare.extend(c.to_string().nfd().filter(|c| !is_combining_mark(*c)));
+        }
+    }
+    bare.nfc().collect()
+}
+
+/// The token stream the full-text table and old snippets work with.
+pub fn filtered(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(|t| t.word)
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+static ENGLISH: LazyLock<Stemmer> = LazyLock::new(|| Stemmer::create(Algorithm::English));
+static RUSSIAN: LazyLock<Stemmer> = LazyLock::new(|| Stemmer::create(Algorithm::Russian));
+static GERMAN: LazyLock<Stemmer> = LazyLock::new(|| Stemmer::create(Algorithm::German));
+
+/// Languages `stem` knows, by the names `detect_language` gives.
+pub const STEMMED: [&str; 3] = ["english", "russian", "german"];
+/// Bytes at the start of a text `detect_language` looks at.
+const LANGUAGE_SAMPLE: usize = 16 * 1024;
+/// How sure `detect_language` has to be, short texts rarely get there.
+const LANGUAGE_CONFIDENCE: f64 = 0.5;
+
+/// Snowball stem of a normalized term, Russian for Cyrillic words, German for
+/// words of a German text and English for other Latin ones. Other words are
+/// their own stem.
+pub fn stem(term: &str, language: &str) -> String {
+    let cyrillic = |c: char| matches!(c, '\u{0400}'..='\u{04ff}');
+
+    if term.chars().any(cyrillic) {
+        RUSSIAN.stem(term).into_owned()
+    } else if language == "german" && term.chars().all(char::is_alphabetic) {
+        GERMAN.stem(term).into_owned()
+    } else if term.chars().all(|c| c.is_ascii_alphabetic() || c == '\'') {
+        ENGLISH.stem(term).into_owned()
+    } else {
+        term.to_string()
+    }
+}
+
+/// The language a text is mostly written in, by its English name in lower
+/// case (`english`, `russian`), empty when it can't be told.
+pub fn detect_language(text: &str) -> String {
+    let mut end = text.len().min(LANGUAGE_SAMPLE);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    whatlang::detect(&text[..end])
+        .filter(|info| info.confidence() >= LANGUAGE_CONFIDENCE)
+        .map(|info| info.lang().eng_name().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn split_apostrophes<'a>(text: &'a str, token: Token<'a>) -> Vec<Token<'a>> {
+    let mut parts = vec![];
+    let mut start = token.start;
+
+    for part in token.word.split(['\'', '\u{2019}']) {
+        if !part.is_empty() {
+            parts.push(Token {
+                word: &text[start..start + part.len()],
+                term: String::new(),
+                start,
+                end: start + part.len(),
+            });
+        }
+        // skip the apostrophe, `’` takes three bytes
+        start += part.len();
+        start += text[start..].chars().next().map_or(0, |c| c.len_utf8());
+    }
+
+    parts
+}
+
+/// Merges words with nothing but a hyphen between them.
+fn join_hyphens<'a>(text: &'a str, words: Vec<Token<'a>>) -> Vec<Token<'a>> {
+    let mut res: Vec<Token> = vec![];
+
+    for token in words {
+        match res.last_mut() {
+            Some(last) if matches!(&text[last.end..token.start], "-" | "\u{2010}") => {
+                last.end = token.end;
+                last.word = &text[last.start..last.end];
+            }
+            _ => res.push(token),
+        }
+    }
+
+    res
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'     // hiragana, katakana
+        | '\u{3400}'..='\u{4dbf}'   // extension A
+        | '\u{4e00}'..='\u{9fff}'   // unified ideographs
+        | '\u{f900}'..='\u{faff}'   // compatibility ideographs
+        | '\u{20000}'..='\u{2ffff}')
+}
+
+/// Unicode segmentation gives every ideograph as a word of its own, runs of
+/// them are turned into overlapping pairs.
+fn cjk_bigrams<'a>(text: &'a str, words: Vec<Token<'a>>) -> Vec<Token<'a>> {
+    let mut res: Vec<Token> = vec![];
+    let mut run: Vec<Token> = vec![];
+
+    let flush = |run: &mut Vec<Token<'a>>, res: &mut Vec<Token<'a>>| {
+        if run.len() == 1 {
+            res.push(run[0].clone());
+        }
+        for pair in run.windows(2) {
+            res.push(Token {
+                word: &text[pair[0].start..pair[1].end],
+                term: String::new(),
+                start: pair[0].start,
+                end: pair[1].end,
+            });
+        }
+        run.clear();
+    };
+
+    for token in words {
+        let single_cjk = token.word.chars().count() == 1 && token.word.chars().all(is_cjk);
+        let adjacent = run.last().is_some_and(|last| last.end == token.start);
+
+        if !single_cjk || !adjacent {
+            flush(&mut run, &mut res);
+        }
+        if single_cjk {
+            run.push(token);
+        } else {
+            res.push(token);
+        }
+    }
+    flush(&mut run, &mut res);
+
+    res
+}
+
+fn compress(text: &str) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+    encoder.write_all(text.as_bytes()).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn decompress(data: &[u8]) -> Option<String> {
+    let mut text = String::new();
+    DeflateDecoder::new(data).read_to_string(&mut text).ok()?;
+
+    Some(text)
+}
+
+impl Db {
+    pub fn word_tokenizer(&self) -> TokenizerOptions {
+        self.setting("word_tokenizer")
+            .map(|s| TokenizerOptions::from_setting(&s))
+            .unwrap_or_default()
+    }
+    /// Saves new tokenizer options and re-indexes the kept texts with them.
+    /// Returns the number of files re-indexed.
+    pub fn set_word_tokenizer(&self, options: TokenizerOptions) -> i64 {
+        self.set_setting("word_tokenizer", &options.to_setting());
+        let done = self.retokenize();
+        self.update_stems(options.stemming);
+
+        done
+    }
+    /// Stems the whole dictionary, or forgets the stems when stemming is off.
+    /// A term is stemmed in the language of one of the files it is in.
+    fn update_stems(&self, stemming: bool) {
+        if !stemming {
+            self.pool.execute("UPDATE term SET stem='';").unwrap();
+            return;
+        }
+
+        let query = "
+	SELECT rowid, term, IFNULL((SELECT language FROM posting JOIN file ON file.rowid = posting.file_id
+		WHERE posting.term_id = term.rowid LIMIT 1), '') AS language
+	FROM term WHERE stem='';
+	";
+        let terms: Vec<(i64, String, String)> = self
+            .pool
+            .prepare(query)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap())
+            .map(|r| {
+                (
+                    r.read::<i64, _>("rowid"),
+                    r.read::<&str, _>("term").to_string(),
+                    r.read::<&str, _>("language").to_string(),
+                )
+            })
+            .collect();
+
+        self.pool.execute("BEGIN IMMEDIATE;").unwrap();
+        let mut stat = self
+            .pool
+            .prepare("UPDATE term SET stem=? WHERE rowid=?;")
+            .unwrap();
+        for (term_id, term, language) in terms {
+            stat.reset().unwrap();
+            stat.bind((1, stem(&term, &language).as_str())).unwrap();
+            stat.bind((2, term_id)).unwrap();
+            stat.next().unwrap();
+        }
+        self.pool.execute("COMMIT;").unwrap();
+    }
+    /// Re-indexes files tokenized by an older build, which cut or normalized
+    /// words differently.
+    pub(crate) fn ensure_word_tokenizer(&self) {
+        let version = TOKENIZER_VERSION.to_string();
+        if self.setting("word_tokenizer_version") == Some(version.clone()) {
+            return;
+        }
+        self.set_word_tokenizer(self.word_tokenizer());
+        self.set_setting("word_tokenizer_version", &version);
+    }
+    /// Cuts the kept text of every live file into words again and tells its
+    /// language, without reading the files. Files indexed before texts were
+    /// kept are left as they are.
+    pub fn retokenize(&self) -> i64 {
+        self.retokenize_files("1", &[])
+    }
+    /// Re-indexes the live files matching `condition` from their kept texts.
+    pub(crate) fn retokenize_files(&self, condition: &str, params: &[Value]) -> i64 {
+        let options = self.word_tokenizer();
+        let query = format!(
+            "SELECT file_id FROM file_text JOIN file ON file.rowid = file_text.file_id WHERE file.deleted=0 AND {};",
+            condition
+        );
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind(params).unwrap();
+        let files: Vec<i64> = stat
+            .into_iter()
+            .map(|r| r.unwrap().read::<i64, _>("file_id"))
+            .collect();
+
+        let mut done = 0;
+        for file_idx in files {
+            let text = match self.source_text(file_idx) {
+                Some(t) => t,
+                None => continue,
+            };
+            let tokens = tokens(&text, &options);
+            let content = filtered(&tokens);
+            let language = detect_language(&text);
+
+            self.pool.execute("BEGIN IMMEDIATE;").unwrap();
+            for query in [
+                "DELETE FROM posting WHERE file_id=?;",
+                "DELETE FROM token WHERE file_id=?;",
+                "DELETE FROM file_fts WHERE rowid=?;",
+            ] {
+                let mut stat = self.pool.prepare(query).unwrap();
+                stat.bind((1, file_idx)).unwrap();
+                stat.next().unwrap();
+            }
+            let mut stat = self
+                .pool
+                .prepare("UPDATE file SET content=?, language=? WHERE rowid=?;")
+                .unwrap();
+            stat.bind((1, content.as_str())).unwrap();
+            stat.bind((2, language.as_str())).unwrap();
+            stat.bind((3, file_idx)).unwrap();
+            stat.next().unwrap();
+            self.fts_insert(file_idx, &content);
+            self.insert_words(file_idx, &tokens, &language, &options);
+            self.pool.execute("COMMIT;").unwrap();
+            done += 1;
+        }
+
+        done
+    }
+    /// Keeps the text extracted from a file as it was, compressed.
+    pub(crate) fn insert_text(&self, file_idx: i64, text: &str) {
+        let mut stat = self
+            .pool
+            .prepare("INSERT OR REPLACE INTO file_text VALUES(?, ?);")
+            .unwrap();
+        stat.bind((1, file_idx)).unwrap();
+        stat.bind((2, compress(text).as_slice())).unwrap();
+        stat.next().expect("Err during inserting file text");
+    }
+    /// Text extracted from a file, `None` for files indexed before the text
+    /// was kept.
+    pub fn source_text(&self, file_idx: i64) -> Option<String> {
+        let mut stat = self
+            .pool
+            .prepare("SELECT text FROM file_text WHERE file_id=?;")
+            .unwrap();
+        stat.bind((1, file_idx)).unwrap();
+
+        stat.into_iter()
+            .map(|r| r.unwrap())
+            .next()
+            .and_then(|r| decompress(r.read::<&[u8], _>("text")))
+    }
+    /// Bytes of the source text the tokens between two positions span.
+    pub fn token_span(&self, file_idx: i64, from: i64, to: i64) -> Option<(usize, usize)> {
+        let query = "
+	SELECT min(start_byte) AS start, max(end_byte) AS end FROM token
+	WHERE file_id=? AND position BETWEEN ? AND ?;
+	";
+        let mut stat = self.pool.prepare(query).unwrap();
+        stat.bind((1, file_idx)).unwrap();
+        stat.bind((2, from)).unwrap();
+        stat.bind((3, to)).unwrap();
+        stat.next().unwrap();
+
+        let start = stat.read::<Option<i64>, _>("start").unwrap()?;
+        let end = stat.read::<Option<i64>, _>("end").unwrap()?;
+        Some((start as usize, end as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(term, start, end)` of each token.
+    fn spans(text: &str, options: TokenizerOptions) -> Vec<(String, usize, usize)> {
+        tokens(text, &options)
+            .into_iter()
+            .map(|t| (t.term, t.start, t.end))
+            .collect()
+    }
+
+    fn span(term: &str, start: usize, end: usize) -> (String, usize, usize) {
+        (term.to_string(), start, end)
+    }
+
+    #[test]
+    fn apostrophes() {
+        let kept = TokenizerOptions::default();
+        let split = TokenizerOptions {
+            apostrophes: false,
+            ..kept
+        };
+
+        assert_eq!(
+            spans("don't go", kept),
+            [span("don't", 0, 5), span("go", 6, 8)]
+        );
+        assert_eq!(
+            spans("don't go", split),
+            [span("don", 0, 3), span("t", 4, 5), span("go", 6, 8)]
+        );
+        // `’` takes three bytes
+        assert_eq!(
+            spans("don\u{2019}t go", split),
+            [span("don", 0, 3), span("t", 6, 7), span("go", 8, 10)]
+        );
+    }
+
+    #[test]
+    fn hyphens() {
+        let split = TokenizerOptions::default();
+        let joined = TokenizerOptions {
+            hyphens: true,
+            ..split
+        };
+
+        assert_eq!(
+            spans("e-mail me", split),
+            [span("e", 0, 1), span("mail", 2, 6), span("me", 7, 9)]
+        );
+        assert_eq!(
+            spans("e-mail me", joined),
+            [span("e-mail", 0, 6), span("me", 7, 9)]
+        );
+        // a hyphen with spaces around it joins nothing
+        assert_eq!(
+            spans("e - mail", joined),
+            [span("e", 0, 1), span("mail", 4, 8)]
+        );
+    }
+
+    #[test]
+    fn cjk_pairs() {
+        let text = "The capital lies in the east, 東京都";
+        let pairs = spans(text, TokenizerOptions::default());
+        assert_eq!(pairs[6..], [span("東京", 30, 36), span("京都", 33, 39)]);
+
+        let single = TokenizerOptions {
+            cjk_bigrams: false,
+            ..TokenizerOptions::default()
+        };
+        let chars = spans(text, single);
+        assert_eq!(
+            chars[6..],
+            [span("東", 30, 33), span("京", 33, 36), span("都", 36, 39)]
+        );
+        // a lone ideograph stays as it is
+        assert_eq!(
+            spans("a 東 b", TokenizerOptions::default()),
+            [span("a", 0, 1), span("東", 2, 5), span("b", 6, 7)]
+        );
+    }
+
+    #[test]
+    fn folded_terms_keep_source_offsets() {
+        let options = TokenizerOptions::default();
+
+        // `ﬁ` is three bytes folded into the two of `fi`, `Ё` is `е`
+        assert_eq!(
+            spans("ﬁle ЁЛКА Straße", options),
+            [
+                span("file", 0, 5),
+                span("елка", 6, 14),
+                span("strasse", 15, 22)
+            ]
+        );
+        let text = "ﬁle ЁЛКА";
+        for t in tokens(text, &options) {
+            assert_eq!(&text[t.start..t.end], t.word);
+        }
+
+        let bare = TokenizerOptions {
+            diacritics: true,
+            ..options
+        };
+        assert_eq!(
+            spans("Café йод", bare),
+            [span("cafe", 0, 5), span("йод", 6, 12)]
+        );
+    }
+}
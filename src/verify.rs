@@ -0,0 +1,271 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::crawler;
+use crate::db::{self, Db};
+use crate::hash;
+
+/// Rows that must point at a live row of another table, and the condition
+/// that finds the ones that don't.
+const ORPHANS: [(&str, &str); 5] = [
+    ("posting", "file_id NOT IN (SELECT rowid FROM file)"),
+    ("token", "file_id NOT IN (SELECT rowid FROM file)"),
+    ("file_text", "file_id NOT IN (SELECT rowid FROM file)"),
+    (
+        "file_fts",
+        "rowid NOT IN (SELECT rowid FROM file WHERE deleted=0)",
+    ),
+    ("term_gram", "term_id NOT IN (SELECT rowid FROM term)"),
+];
+
+/// Something wrong with the index.
+#[derive(Debug, Clone)]
+pub enum Issue {
+    /// SQLite found the database damaged, an index out of order included.
+    Corrupt(String),
+    /// Rows of `table` pointing at files or terms that are gone.
+    Orphans {
+        table: &'static str,
+        count: i64,
+    },
+    /// Terms the fuzzy search can't find as they have no trigrams.
+    MissingGrams(i64),
+    /// Live files missing from the full-text table.
+    MissingFullText(i64),
+    /// A live file has postings of terms missing from the dictionary.
+    BrokenPostings {
+        file_idx: i64,
+        path: String,
+    },
+    MissingOnDisk {
+        file_idx: i64,
+        path: String,
+    },
+    /// The file on disk is not what was indexed.
+    Changed {
+        file_idx: i64,
+        path: String,
+    },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::Corrupt(e) => write!(f, "database is damaged: {}", e),
+            Issue::Orphans { table, count } => {
+                write!(f, "{} rows of {} point at nothing", count, table)
+            }
+            Issue::MissingGrams(count) => write!(f, "{} terms have no trigrams", count),
+            Issue::MissingFullText(count) => {
+                write!(f, "{} files are missing from the full-text table", count)
+            }
+            Issue::BrokenPostings { path, .. } => {
+                write!(f, "{}: postings of unknown terms", path)
+            }
+            Issue::MissingOnDisk { path, .. } => write!(f, "{}: no longer on disk", path),
+            Issue::Changed { path, .. } => write!(f, "{}: changed since it was indexed", path),
+        }
+    }
+}
+
+impl Db {
+    /// Checks the index against itself and the files on disk. Every live
+    /// file is hashed again, so this takes as long as reading the library.
+    pub fn verify(&self) -> Vec<Issue> {
+        let mut issues = vec![];
+
+        for r in self
+            .pool
+            .prepare("PRAGMA integrity_check;")
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap())
+        {
+            let message = r.read::<&str, _>(0);
+            if message != "ok" {
+                issues.push(Issue::Corrupt(message.to_string()));
+            }
+        }
+        if let Err(e) = self
+            .pool
+            .execute("INSERT INTO file_fts(file_fts) VALUES('integrity-check');")
+        {
+            issues.push(Issue::Corrupt(format!("full-text table: {}", e)));
+        }
+
+        for (table, condition) in ORPHANS {
+            let count = self.count(&format!(
+                "SELECT count(*) AS count FROM {} WHERE {};",
+                table, condition
+            ));
+            if count > 0 {
+                issues.push(Issue::Orphans { table, count });
+            }
+        }
+
+        let count =
+            self.count("SELECT count(*) AS count FROM term WHERE rowid NOT IN (SELECT term_id FROM term_gram);");
+        if count > 0 {
+            issues.push(Issue::MissingGrams(count));
+        }
+        let count = self.count(
+            "SELECT count(*) AS count FROM file WHERE deleted=0 AND rowid NOT IN (SELECT rowid FROM file_fts);",
+        );
+        if count > 0 {
+            issues.push(Issue::MissingFullText(count));
+        }
+
+        let query = "
+	SELECT DISTINCT file.rowid AS rowid, path FROM posting JOIN file ON file.rowid = posting.file_id
+	WHERE file.deleted=0 AND posting.term_id NOT IN (SELECT rowid FROM term);
+	";
+        for (file_idx, path) in self.files_of(query) {
+            issues.push(Issue::BrokenPostings { file_idx, path });
+        }
+
+        let query = "
+	SELECT rowid, path, content_hash FROM file WHERE deleted=0 ORDER BY path;
+	";
+        for r in self
+            .pool
+            .prepare(query)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap())
+        {
+            let file_idx = r.read::<i64, _>("rowid");
+            let path = r.read::<&str, _>("path").to_string();
+
+            match hash::content_hash(Path::new(&path)) {
+                Err(_) => issues.push(Issue::MissingOnDisk { file_idx, path }),
+                Ok(h) if h != r.read::<&str, _>("content_hash") => {
+                    issues.push(Issue::Changed { file_idx, path })
+                }
+                Ok(_) => (),
+            }
+        }
+
+        issues
+    }
+    /// Fixes what `verify` found. Broken and changed files are extracted
+    /// again, nothing else is re-read.
+    pub fn repair(&self, issues: &[Issue]) {
+        // every message of a damaged database takes the same rebuild
+        if issues
+            .iter()
+            .any(|issue| matches!(issue, Issue::Corrupt(_)))
+        {
+            self.pool.execute("REINDEX;").unwrap();
+            self.pool.execute("DROP TABLE IF EXISTS file_fts;").unwrap();
+            self.ensure_fts();
+        }
+        for issue in issues {
+            match issue {
+                Issue::Corrupt(_) => (),
+                Issue::Orphans { table, .. } => {
+                    let (_, condition) = ORPHANS.iter().find(|(t, _)| t == table).unwrap();
+                    self.pool
+                        .execute(format!("DELETE FROM {} WHERE {};", table, condition))
+                        .unwrap();
+                }
+                Issue::MissingGrams(_) => {
+                    let query = "
+	SELECT rowid, term FROM term WHERE rowid NOT IN (SELECT term_id FROM term_gram);
+	";
+                    let terms: Vec<(i64, String)> = self
+                        .pool
+                        .prepare(query)
+                        .unwrap()
+                        .into_iter()
+                        .map(|r| r.unwrap())
+                        .map(|r| {
+                            (
+                                r.read::<i64, _>("rowid"),
+                                r.read::<&str, _>("term").to_string(),
+                            )
+                        })
+                        .collect();
+                    self.pool.execute("BEGIN IMMEDIATE;").unwrap();
+                    for (term_id, term) in terms {
+                        self.insert_term_grams(term_id, &term);
+                    }
+                    self.pool.execute("COMMIT;").unwrap();
+                }
+                Issue::MissingFullText(_) => {
+                    let query = "
+	INSERT INTO file_fts(rowid, content)
+	SELECT rowid, content FROM file WHERE deleted=0 AND rowid NOT IN (SELECT rowid FROM file_fts);
+	";
+                    self.pool.execute(query).unwrap();
+                }
+                Issue::BrokenPostings { file_idx, .. } | Issue::Changed { file_idx, .. } => {
+                    self.reindex(*file_idx);
+                }
+                Issue::MissingOnDisk { file_idx, .. } => self.tombstone(*file_idx),
+            }
+        }
+        self.fts_purge();
+    }
+    /// Extracts a file again in place of its indexed copy.
+    fn reindex(&self, file_idx: i64) {
+        let (library_id, seen_run, path) = {
+            let mut stat = self
+                .pool
+                .prepare("SELECT library_id, IFNULL(seen_run, 0) AS seen_run, path FROM file WHERE rowid=?;")
+                .unwrap();
+            stat.bind((1, file_idx)).unwrap();
+            stat.next().unwrap();
+            (
+                stat.read::<i64, _>("library_id").unwrap(),
+                stat.read::<i64, _>("seen_run").unwrap(),
+                stat.read::<String, _>("path").unwrap(),
+            )
+        };
+        let meta = match fs::metadata(&path) {
+            Ok(m) => m,
+            Err(_) => return,
+        };
+        let file = crawler::describe(Path::new(&path), &meta);
+        let (content, author) = db::extract(&file);
+
+        let options = self.word_tokenizer();
+
+        self.pool.execute("BEGIN IMMEDIATE;").unwrap();
+        self.tombstone(file_idx);
+        self.index_file(
+            library_id,
+            seen_run,
+            &file,
+            content.as_deref(),
+            &author,
+            &options,
+        );
+        self.pool.execute("COMMIT;").unwrap();
+    }
+    fn count(&self, query: &str) -> i64 {
+        self.pool
+            .prepare(query)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap())
+            .next()
+            .map(|r| r.read::<i64, _>("count"))
+            .unwrap_or(0)
+    }
+    /// `(rowid, path)` pairs a query returns.
+    fn files_of(&self, query: &str) -> Vec<(i64, String)> {
+        self.pool
+            .prepare(query)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap())
+            .map(|r| {
+                (
+                    r.read::<i64, _>("rowid"),
+                    r.read::<&str, _>("path").to_string(),
+                )
+            })
+            .collect()
+    }
+}